@@ -0,0 +1,349 @@
+use crate::{error::Error, secret::Secrets, user::User, utils::write_atomic, MinecraftAuth};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs,
+    path::Path,
+};
+
+/// Version of the accounts file written by this crate
+pub const STORE_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum AccountStoreError {
+    NoFoundAccount(String),
+    AlreadyExist(String),
+    UnsupportedVersion(u32),
+}
+
+impl Display for AccountStoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            AccountStoreError::NoFoundAccount(name) => format!("No account found: {}", name),
+            AccountStoreError::AlreadyExist(name) => format!("Account already exist: {}", name),
+            AccountStoreError::UnsupportedVersion(v) => {
+                format!("Unsupported accounts file version: {}", v)
+            }
+        };
+
+        f.write_str(&message)
+    }
+}
+
+/// All accounts saved on `users_accounts.json`, tokens are
+/// saved with the secret backend of `MinecraftAuth`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccountStore {
+    pub version: u32,
+
+    /// Login of the account currently used
+    pub active: Option<String>,
+
+    accounts: Vec<User>,
+
+    /// Accounts to delete from the secret backend on save
+    #[serde(skip)]
+    removed: Vec<String>,
+}
+
+impl Default for AccountStore {
+    fn default() -> Self {
+        Self {
+            version: STORE_VERSION,
+            active: None,
+            accounts: vec![],
+            removed: vec![],
+        }
+    }
+}
+
+impl AccountStore {
+    fn path(app: &MinecraftAuth) -> String {
+        format!("{}/users_accounts.json", app.path)
+    }
+
+    /// Load the accounts file, an empty store is returned if the file
+    /// doesn't exist. Old versions of the file are migrated
+    pub fn load(app: &MinecraftAuth) -> Result<Self, Error> {
+        let p = AccountStore::path(app);
+        if !Path::new(&p).is_file() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&p)?;
+        if content.trim().is_empty() {
+            return Ok(Self::default());
+        }
+
+        let root: Value = serde_json::from_str(&content)?;
+        let mut store: Self = serde_json::from_value(migrate(root)?)?;
+        if !app.secrets.inline() {
+            for user in &mut store.accounts {
+                if let Some(secrets) = app.secrets.get(app, &user.login)? {
+                    secrets.apply(user);
+                }
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Write the accounts file, the content is written on a temporary
+    /// file then renamed to never leave a partial file
+    pub fn save(&self, app: &MinecraftAuth) -> Result<(), Error> {
+        fs::create_dir_all(&app.path)?;
+
+        let content = if app.secrets.inline() {
+            serde_json::to_string_pretty(self)?
+        } else {
+            for login in &self.removed {
+                app.secrets.delete(app, login)?;
+            }
+
+            let mut store = self.clone();
+            for user in &mut store.accounts {
+                app.secrets
+                    .set(app, &user.login, &Secrets::from_user(user))?;
+                Secrets::clear(user);
+            }
+
+            serde_json::to_string_pretty(&store)?
+        };
+
+        write_atomic(AccountStore::path(app), content)?;
+
+        Ok(())
+    }
+
+    pub fn accounts(&self) -> &[User] {
+        &self.accounts
+    }
+
+    /// Find an account with its login
+    pub fn get(&self, login: &str) -> Option<&User> {
+        self.accounts.iter().find(|u| u.login == login)
+    }
+
+    /// Return the active account
+    pub fn active(&self) -> Option<&User> {
+        self.get(self.active.as_ref()?)
+    }
+
+    pub fn set_active(&mut self, login: &str) -> Result<(), Error> {
+        if self.get(login).is_none() {
+            return Err(AccountStoreError::NoFoundAccount(login.into()).into());
+        }
+
+        self.active = Some(login.into());
+        Ok(())
+    }
+
+    /// Add a new account, the first account added become the active one
+    pub fn add(&mut self, user: User) -> Result<(), Error> {
+        if self.get(&user.login).is_some() {
+            return Err(AccountStoreError::AlreadyExist(user.login).into());
+        }
+
+        if self.active.is_none() {
+            self.active = Some(user.login.clone());
+        }
+
+        self.accounts.push(user);
+        Ok(())
+    }
+
+    /// Replace the account with the same login. Logins are unique, an
+    /// account of another provider with this login is never replaced
+    pub fn update(&mut self, user: User) -> Result<(), Error> {
+        let account = self
+            .accounts
+            .iter_mut()
+            .find(|u| u.login == user.login)
+            .ok_or_else(|| AccountStoreError::NoFoundAccount(user.login.clone()))?;
+
+        if account.provider != user.provider {
+            return Err(AccountStoreError::AlreadyExist(user.login).into());
+        }
+
+        *account = user;
+        Ok(())
+    }
+
+    /// Add the account or update it if already exist
+    pub fn upsert(&mut self, user: User) -> Result<(), Error> {
+        if self.get(&user.login).is_some() {
+            self.update(user)
+        } else {
+            self.add(user)
+        }
+    }
+
+    pub fn remove(&mut self, login: &str) -> Result<User, Error> {
+        let index = self
+            .accounts
+            .iter()
+            .position(|u| u.login == login)
+            .ok_or_else(|| AccountStoreError::NoFoundAccount(login.into()))?;
+
+        if self.active.as_deref() == Some(login) {
+            self.active = None;
+        }
+
+        self.removed.push(login.to_string());
+        Ok(self.accounts.remove(index))
+    }
+}
+
+/// Convert an accounts file of any version to the current version
+fn migrate(mut root: Value) -> Result<Value, Error> {
+    let mut version = root["version"].as_u64().unwrap_or(0) as u32;
+    if version > STORE_VERSION {
+        return Err(AccountStoreError::UnsupportedVersion(version).into());
+    }
+
+    while version < STORE_VERSION {
+        root = match version {
+            0 => migrate_v0(root),
+            1 => migrate_v1(root),
+            v => return Err(AccountStoreError::UnsupportedVersion(v).into()),
+        };
+        version += 1;
+    }
+
+    Ok(root)
+}
+
+/// Version 0 is `{"users": {"<username>": {"uuid", "access_token", "client_token"}}}`
+fn migrate_v0(root: Value) -> Value {
+    let mut accounts = vec![];
+    if let Some(users) = root["users"].as_object() {
+        for (username, user) in users {
+            let mut user = user.clone();
+            user["username"] = Value::String(username.clone());
+            accounts.push(user);
+        }
+    }
+
+    let active = accounts.last().map(|u| u["username"].clone());
+    json!({
+        "version": 1,
+        "active": active,
+        "accounts": accounts,
+    })
+}
+
+/// Version 1 use `username` as key, it was the login of the account
+fn migrate_v1(mut root: Value) -> Value {
+    if let Some(accounts) = root["accounts"].as_array_mut() {
+        for user in accounts {
+            user["login"] = user["username"].clone();
+        }
+    }
+
+    root["version"] = json!(2);
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::Provider;
+
+    fn app(dir: &tempfile::TempDir) -> MinecraftAuth {
+        MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into())
+    }
+
+    #[test]
+    fn multiple_accounts_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir);
+
+        let first = User::new("First".into(), "1".into(), "c1".into(), "a1".into());
+        let second = User::offline("Second".into());
+        first.save_on_file(&app).unwrap();
+        second.save_on_file(&app).unwrap();
+
+        let store = AccountStore::load(&app).unwrap();
+        assert_eq!(store.accounts(), &[first.clone(), second.clone()]);
+        assert_eq!(store.active(), Some(&first));
+
+        let mut updated = first.clone();
+        updated.access_token = "a2".into();
+        updated.save_on_file(&app).unwrap();
+        second.disconnect(&app).unwrap();
+
+        let store = AccountStore::load(&app).unwrap();
+        assert_eq!(store.accounts(), &[updated]);
+        assert!(!Path::new(&format!("{}.tmp", AccountStore::path(&app))).exists());
+    }
+
+    #[test]
+    fn same_login_on_two_providers() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir);
+
+        let mut mojang = User::new("Player".into(), "1".into(), "c1".into(), "a1".into());
+        mojang.login = "player@mail.com".into();
+        let yggdrasil = User {
+            access_token: "a2".into(),
+            provider: Provider::Yggdrasil {
+                url: "https://example.com/api/yggdrasil".into(),
+            },
+            ..mojang.clone()
+        };
+
+        mojang.save_on_file(&app).unwrap();
+        assert!(yggdrasil.save_on_file(&app).is_err());
+
+        let store = AccountStore::load(&app).unwrap();
+        assert_eq!(store.accounts(), &[mojang]);
+    }
+
+    #[test]
+    fn active_account() {
+        let mut store = AccountStore::default();
+        store.add(User::offline("First".into())).unwrap();
+        store.add(User::offline("Second".into())).unwrap();
+        assert!(store.add(User::offline("Second".into())).is_err());
+
+        store.set_active("Second").unwrap();
+        assert_eq!(store.active().unwrap().username, "Second");
+        assert!(store.set_active("Third").is_err());
+
+        store.remove("Second").unwrap();
+        assert_eq!(store.active(), None);
+    }
+
+    #[test]
+    fn migrate_from_v0() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir);
+        fs::write(
+            AccountStore::path(&app),
+            r#"{"users":{"Player":{"uuid":"id","access_token":"a","client_token":"c"}}}"#,
+        )
+        .unwrap();
+
+        let store = AccountStore::load(&app).unwrap();
+        assert_eq!(store.version, STORE_VERSION);
+        assert_eq!(
+            store.active(),
+            Some(&User::new(
+                "Player".into(),
+                "id".into(),
+                "c".into(),
+                "a".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn newer_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(&dir);
+        fs::write(AccountStore::path(&app), r#"{"version":99}"#).unwrap();
+
+        assert!(AccountStore::load(&app).is_err());
+    }
+}
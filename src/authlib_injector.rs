@@ -0,0 +1,258 @@
+use crate::{
+    downloader::download_file, error::Error, provider::Provider, user::User, utils::write_atomic,
+    MinecraftAuth,
+};
+use log::{info, warn};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use std::{fs, io};
+
+/// Information of the latest authlib-injector release
+const AUTHLIB_INJECTOR_LATEST: &str = "https://authlib-injector.yushi.moe/artifact/latest.json";
+
+/// authlib-injector java agent, needed to play with
+/// an account of a third-party yggdrasil server
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthlibInjector {
+    pub jar_path: String,
+
+    /// Version of the downloaded jar
+    pub version: String,
+
+    /// Api root of the yggdrasil server
+    pub api_root: String,
+
+    /// Metadata of the server encoded in base64
+    pub metadata: String,
+}
+
+impl AuthlibInjector {
+    /// Load authlib-injector for this user from the cache, `None` if
+    /// the user doesn't need it or if it's not downloaded yet
+    pub fn from_cache(app: &MinecraftAuth, user: &User) -> Option<Self> {
+        let api_root = api_root(user)?;
+        let jar = CachedJar::verified(app)?;
+
+        let metadata = fs::read_to_string(metadata_path(app, api_root)).ok()?;
+        Some(Self {
+            jar_path: jar_path(app),
+            version: jar.version,
+            api_root: api_root.to_string(),
+            metadata: base64::encode(metadata),
+        })
+    }
+
+    /// Jvm arguments to add before the main class
+    pub fn jvm_args(&self) -> Vec<String> {
+        vec![
+            format!("-javaagent:{}={}", self.jar_path, self.api_root),
+            format!("-Dauthlibinjector.yggdrasil.prefetched={}", self.metadata),
+        ]
+    }
+}
+
+fn api_root(user: &User) -> Option<&str> {
+    match &user.provider {
+        Provider::Yggdrasil { url } => Some(url),
+        _ => None,
+    }
+}
+
+fn jar_path(app: &MinecraftAuth) -> String {
+    format!("{}/authlib-injector/authlib-injector.jar", app.path)
+}
+
+/// Version and sha256 of the downloaded jar
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedJar {
+    version: String,
+    sha256: String,
+}
+
+impl CachedJar {
+    fn path(app: &MinecraftAuth) -> String {
+        format!("{}/authlib-injector/version.json", app.path)
+    }
+
+    /// Return the cached jar info if the jar still has the right sha256
+    fn verified(app: &MinecraftAuth) -> Option<Self> {
+        let content = fs::read_to_string(CachedJar::path(app)).ok()?;
+        let cached: CachedJar = serde_json::from_str(&content).ok()?;
+        let found = file_sha256(&jar_path(app)).ok()?;
+
+        found.eq_ignore_ascii_case(&cached.sha256).then_some(cached)
+    }
+}
+
+fn file_sha256(path: &str) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Download the latest jar and check it with the sha256 given by the server
+async fn download_jar(app: &MinecraftAuth, latest_url: &str) -> Result<(), Error> {
+    let latest: Value = app.client().get(latest_url).send().await?.json().await?;
+    let url = latest["download_url"]
+        .as_str()
+        .ok_or("download_url str")?
+        .to_string();
+    let expected = latest["checksums"]["sha256"]
+        .as_str()
+        .ok_or("checksums.sha256 str")?
+        .to_string();
+    let version = match &latest["version"] {
+        Value::String(version) => version.clone(),
+        version => version.to_string(),
+    };
+
+    info!("Download authlib-injector {}", version);
+    let jar = jar_path(app);
+    download_file(app, url, jar.clone()).await?;
+
+    let found = file_sha256(&jar)?;
+    if !found.eq_ignore_ascii_case(&expected) {
+        fs::remove_file(&jar)?;
+        return Err(Error::HashMismatch {
+            path: jar,
+            expected,
+            found,
+        });
+    }
+
+    let cached = CachedJar {
+        version,
+        sha256: found,
+    };
+    write_atomic(CachedJar::path(app), serde_json::to_string(&cached)?)?;
+    Ok(())
+}
+
+fn metadata_path(app: &MinecraftAuth, api_root: &str) -> String {
+    let hash = Md5::digest(api_root.as_bytes());
+    let name: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}/authlib-injector/{}.json", app.path, name)
+}
+
+/// Download authlib-injector and the server metadata if the user
+/// is connected to a third-party yggdrasil server
+pub async fn prepare(app: &MinecraftAuth, user: &User) -> Result<Option<AuthlibInjector>, Error> {
+    prepare_with(app, user, AUTHLIB_INJECTOR_LATEST).await
+}
+
+async fn prepare_with(
+    app: &MinecraftAuth,
+    user: &User,
+    latest_url: &str,
+) -> Result<Option<AuthlibInjector>, Error> {
+    let api_root = match api_root(user) {
+        Some(url) => url,
+        None => return Ok(None),
+    };
+
+    if CachedJar::verified(app).is_none() {
+        download_jar(app, latest_url).await?;
+    }
+
+    let metadata_path = metadata_path(app, api_root);
+    match app.client().get(api_root).send().await {
+        Ok(response) if response.status().is_success() => {
+            fs::create_dir_all(format!("{}/authlib-injector", app.path))?;
+            fs::write(&metadata_path, response.text().await?)?;
+        }
+        _ => warn!("Can't fetch metadata of {}, use cached one", api_root),
+    }
+
+    AuthlibInjector::from_cache(app, user)
+        .map(Some)
+        .ok_or_else(|| format!("No metadata found for {}", api_root).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, Response};
+    use serde_json::json;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn download_and_cache() {
+        let url = serve(|req| match req.path.as_str() {
+            "/latest.json" | "/bad.json" => {
+                let sha256 = match req.path.as_str() {
+                    "/latest.json" => file_sha256_of(b"jar"),
+                    _ => "0".repeat(64),
+                };
+                Response::json(
+                    200,
+                    json!({
+                        "version": "1.0",
+                        "download_url": format!("http://{}/agent.jar", req.header("host").unwrap()),
+                        "checksums": { "sha256": sha256 },
+                    }),
+                )
+            }
+            "/agent.jar" => Response::new(200, "jar"),
+            "/api" => Response::json(200, json!({ "meta": {} })),
+            _ => Response::new(404, ""),
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+        let mut user = User::offline("Player".into());
+        assert_eq!(
+            prepare_with(&app, &user, &format!("{}/latest.json", url))
+                .await
+                .unwrap(),
+            None
+        );
+
+        user.provider = Provider::Yggdrasil {
+            url: format!("{}/api", url),
+        };
+        let injector = prepare_with(&app, &user, &format!("{}/latest.json", url))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(&injector.jar_path).unwrap(), "jar");
+        assert_eq!(injector.version, "1.0");
+        assert_eq!(
+            injector.jvm_args()[1],
+            format!(
+                "-Dauthlibinjector.yggdrasil.prefetched={}",
+                base64::encode(r#"{"meta":{}}"#)
+            )
+        );
+        assert_eq!(
+            AuthlibInjector::from_cache(&app, &user),
+            Some(injector.clone())
+        );
+
+        // A changed jar is not used and downloaded again
+        fs::write(&injector.jar_path, "truncated").unwrap();
+        assert_eq!(AuthlibInjector::from_cache(&app, &user), None);
+        let err = prepare_with(&app, &user, &format!("{}/bad.json", url))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::HashMismatch { .. }));
+        assert!(!Path::new(&injector.jar_path).exists());
+
+        prepare_with(&app, &user, &format!("{}/latest.json", url))
+            .await
+            .unwrap();
+        assert_eq!(fs::read_to_string(&injector.jar_path).unwrap(), "jar");
+    }
+
+    fn file_sha256_of(content: &[u8]) -> String {
+        let hash = Sha256::digest(content);
+        hash.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
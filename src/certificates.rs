@@ -0,0 +1,181 @@
+use crate::{
+    error::Error,
+    user::User,
+    utils::{json_response, now, parse_rfc3339},
+    MinecraftAuth,
+};
+use serde::{Deserialize, Serialize};
+
+/// RSA key pair of the player encoded in PEM
+#[derive(Debug, Clone, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPair {
+    /// Saved with the secret backend, like tokens
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub private_key: String,
+    pub public_key: String,
+}
+
+/// Certificates used to sign chat messages since 1.19
+#[derive(Debug, Clone, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerCertificates {
+    pub key_pair: KeyPair,
+
+    /// Signature of the public key by mojang, encoded in base64
+    pub public_key_signature: String,
+
+    /// Signature of the public key with the player uuid, used since 1.19.1
+    #[serde(default)]
+    pub public_key_signature_v2: String,
+
+    /// Date when the keys can't be used anymore
+    pub expires_at: String,
+
+    /// Date after which new keys should be asked
+    pub refreshed_after: String,
+}
+
+impl PlayerCertificates {
+    /// Expiration as seconds since unix epoch
+    pub fn expires_at_secs(&self) -> Option<u64> {
+        parse_rfc3339(&self.expires_at)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at_secs().is_none_or(|t| t <= now())
+    }
+
+    /// New certificates should be asked, they are expired
+    /// or mojang wants them refreshed
+    pub fn need_refresh(&self) -> bool {
+        self.key_pair.private_key.is_empty()
+            || self.is_expired()
+            || parse_rfc3339(&self.refreshed_after).is_none_or(|t| t <= now())
+    }
+
+    /// Decoded signature of the public key, use the v2 one if available
+    pub fn signature(&self) -> Result<Vec<u8>, Error> {
+        let signature = if self.public_key_signature_v2.is_empty() {
+            &self.public_key_signature
+        } else {
+            &self.public_key_signature_v2
+        };
+
+        Ok(base64::decode(signature).map_err(|e| e.to_string())?)
+    }
+}
+
+/// Ask new certificates for the user
+pub async fn fetch(app: &MinecraftAuth, user: &User) -> Result<PlayerCertificates, Error> {
+    let response = app
+        .client()
+        .post(format!(
+            "{}/player/certificates",
+            app.api.services_for(user)?
+        ))
+        .bearer_auth(&user.access_token)
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+/// Return the certificates of the user, new ones are fetched when
+/// needed and saved with the account on the users file
+pub async fn certificates(
+    app: &MinecraftAuth,
+    user: &mut User,
+) -> Result<PlayerCertificates, Error> {
+    if let Some(certificates) = &user.certificates {
+        if !certificates.need_refresh() {
+            return Ok(certificates.clone());
+        }
+    }
+
+    let certificates = fetch(app, user).await?;
+    user.certificates = Some(certificates.clone());
+    user.save_on_file(app)?;
+
+    Ok(certificates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        secret::EncryptedFileBackend,
+        test_server::{serve, Response},
+        ApiUrls,
+    };
+    use serde_json::json;
+    use std::{
+        fs,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    #[tokio::test]
+    async fn fetch_and_cache() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        let url = serve(move |req| {
+            assert_eq!(req.path, "/player/certificates");
+            assert_eq!(req.header("authorization"), Some("Bearer access"));
+            count.fetch_add(1, Ordering::SeqCst);
+
+            Response::json(
+                200,
+                json!({
+                    "keyPair": { "privateKey": "private-pem", "publicKey": "public-pem" },
+                    "publicKeySignature": base64::encode("v1"),
+                    "publicKeySignatureV2": base64::encode("v2"),
+                    "expiresAt": "2999-07-28T01:16:55.212806Z",
+                    "refreshedAfter": "2999-07-27T17:16:55.212806Z",
+                }),
+            )
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into())
+            .with_secret_backend(Arc::new(EncryptedFileBackend::new("passphrase".into())));
+        app.api = ApiUrls::with_base_url(&url);
+        let mut user = User::new(
+            "Player".into(),
+            "uuid".into(),
+            "client".into(),
+            "access".into(),
+        );
+
+        let first = certificates(&app, &mut user).await.unwrap();
+        assert_eq!(first.key_pair.private_key, "private-pem");
+        assert_eq!(first.signature().unwrap(), b"v2");
+        assert!(!first.need_refresh());
+
+        let mut saved = User::from_config(&app, "Player".into()).unwrap();
+        assert_eq!(certificates(&app, &mut saved).await.unwrap(), first);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        let accounts = fs::read_to_string(dir.path().join("users_accounts.json")).unwrap();
+        assert!(accounts.contains("public-pem"));
+        assert!(!accounts.contains("private-pem"));
+
+        saved.certificates.as_mut().unwrap().expires_at = "2000-01-01T00:00:00Z".into();
+        certificates(&app, &mut saved).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn expiration_date() {
+        let certificates = PlayerCertificates {
+            expires_at: "2022-07-28T01:16:55.212806Z".into(),
+            ..Default::default()
+        };
+
+        assert_eq!(certificates.expires_at_secs(), Some(1658971015));
+        assert!(certificates.is_expired());
+    }
+}
@@ -1,11 +1,43 @@
+use crate::utils::parse_rfc3339;
 use serde::{Deserialize, Serialize};
 
+/// Kind of version, from the `type` field of the list of versions
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionType {
+    Release,
+    Snapshot,
+    OldBeta,
+    OldAlpha,
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Version {
     pub id: String,
     #[serde(rename = "type")]
-    pub t: String,
+    pub t: VersionType,
     pub url: String,
+
+    /// Sha1 of the version manifest, only in `version_manifest_v2.json`
+    #[serde(default)]
+    pub sha1: Option<String>,
+    #[serde(default)]
+    pub time: String,
+    #[serde(default, rename = "releaseTime")]
+    pub release_time: String,
+}
+
+impl Version {
+    pub fn version_type(&self) -> VersionType {
+        self.t
+    }
+
+    /// Release time as seconds since unix epoch
+    pub fn release_time_secs(&self) -> Option<u64> {
+        parse_rfc3339(&self.release_time)
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -19,3 +51,25 @@ pub struct Latest {
     pub release: String,
     pub snapshot: String,
 }
+
+impl ManifestVersion {
+    /// Find a version with its id, `latest-release` (or `latest`)
+    /// and `latest-snapshot` are resolved with `latest`
+    pub fn find(&self, id: &str) -> Option<&Version> {
+        let id = match id {
+            "latest" | "latest-release" => &self.latest.release,
+            "latest-snapshot" => &self.latest.snapshot,
+            id => id,
+        };
+
+        self.versions.iter().find(|v| v.id == id)
+    }
+
+    pub fn latest_release(&self) -> Option<&Version> {
+        self.find("latest-release")
+    }
+
+    pub fn latest_snapshot(&self) -> Option<&Version> {
+        self.find("latest-snapshot")
+    }
+}
@@ -1,20 +1,44 @@
-use futures::StreamExt;
-use reqwest::Client;
+use futures::{stream, StreamExt};
+use log::warn;
+use rand::Rng;
+use reqwest::{
+    header::{CONTENT_RANGE, RANGE, RETRY_AFTER},
+    Client, Response, StatusCode,
+};
 use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use std::{
-    fs::{create_dir_all, File},
-    io::Write,
+    fs::{self, create_dir_all},
+    io,
     iter::Sum,
     path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc::UnboundedSender,
 };
+pub use tokio_util::sync::CancellationToken;
+
+use crate::{error, mirror::MirrorConfig, network::default_client, MinecraftAuth};
 
-use crate::error;
+/// Number of files downloaded at the same time by default
+pub const DEFAULT_CONCURRENCY: usize = 8;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FileInfo {
     pub url: String,
     pub path: String,
     pub size: u64,
+
+    /// Expected sha1 of the file, checked after the download
+    #[serde(default)]
+    pub sha1: Option<String>,
 }
 
 impl<'a> Sum<&'a FileInfo> for u64 {
@@ -25,43 +49,1029 @@ impl<'a> Sum<&'a FileInfo> for u64 {
 
 impl FileInfo {
     pub fn new(url: String, path: String, size: u64) -> Self {
-        Self { url, path, size }
+        Self {
+            url,
+            path,
+            size,
+            sha1: None,
+        }
+    }
+
+    pub fn with_sha1(mut self, sha1: String) -> Self {
+        self.sha1 = Some(sha1);
+        self
     }
+
+    /// The file is missing or doesn't have the right size, with
+    /// `verify_hash` the sha1 of the file is checked too
+    pub fn needs_download(&self, verify_hash: bool) -> bool {
+        match fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() == self.size => {}
+            _ => return true,
+        }
+
+        match &self.sha1 {
+            Some(expected) if verify_hash => file_sha1(&self.path)
+                .map(|found| !found.eq_ignore_ascii_case(expected))
+                .unwrap_or(true),
+            _ => false,
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-fn just_path(path: &str) -> &str {
-    let filename_size = path.split('/').last().unwrap().len();
-    &path[..path.len() - filename_size]
+/// Sha1 of a file on disk
+pub fn file_sha1(path: &str) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
 }
 
-fn path_for_file(path: &str) {
-    let f = Path::new(path);
-    if !f.exists() {
-        create_folder(path);
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes that can be received now, negative when
+    /// more was received than allowed
+    available: f64,
+    last: Instant,
+}
+
+/// Max bytes per second received by all downloads using it. Clones
+/// share the same limit, it can be changed while files are downloading
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// 0 for no limit
+    bytes_per_sec: Arc<AtomicU64>,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(None)
     }
 }
 
-fn create_folder(folder: &str) {
-    create_dir_all(folder).unwrap();
+impl RateLimit {
+    pub fn new(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            bytes_per_sec: Arc::new(AtomicU64::new(bytes_per_sec.unwrap_or(0))),
+            bucket: Arc::new(Mutex::new(Bucket {
+                available: 0.0,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        match self.bytes_per_sec.load(Ordering::Relaxed) {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Change the limit, `None` to remove it
+    pub fn set_limit(&self, bytes_per_sec: Option<u64>) {
+        self.bytes_per_sec
+            .store(bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    /// Count received bytes and wait until the limit allows more
+    async fn consume(&self, bytes: u64) {
+        let mut bytes = bytes as f64;
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last).as_secs_f64();
+                bucket.last = now;
+
+                let rate = match self.limit() {
+                    Some(rate) => rate as f64,
+                    None => {
+                        bucket.available = 0.0;
+                        return;
+                    }
+                };
+
+                // Keep at most one second of unused bandwidth
+                bucket.available = (bucket.available + elapsed * rate).min(rate) - bytes;
+                bytes = 0.0;
+                if bucket.available >= 0.0 {
+                    return;
+                }
+
+                Duration::from_secs_f64(-bucket.available / rate)
+            };
+
+            // Wake up often to see changes of the limit
+            tokio::time::sleep(wait.min(Duration::from_millis(100))).await;
+        }
+    }
 }
 
-pub async fn download_file(url: String, path: String) -> Result<(), error::Error> {
-    let client = Client::new();
-    let response = client.get(&url).send().await?;
+/// How failed downloads are tried again. The delay double on each
+/// try, a random part is added to not retry all files at the same time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of tries after the first one
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
 
-    path_for_file(just_path(&path));
-    let mut file = File::create(&path)?;
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never try again
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
 
-    let mut stream = response.bytes_stream();
-    while let Some(item) = stream.next().await {
-        let chunk = item
-            .map_err(|_| "Error while downloading file bytes".to_string())
-            .unwrap();
+    /// Delay before the try number `attempt`, starting at 1
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Errors worth another try, network errors and server errors
+fn is_retryable(error: &error::Error) -> bool {
+    match error {
+        error::Error::Reqwest(_) | error::Error::HashMismatch { .. } => true,
+        error::Error::Http { status, .. } => {
+            *status == 408 || *status == 429 || (500..600).contains(status)
+        }
+        error::Error::Other(_) => true,
+        _ => false,
+    }
+}
+
+/// Size of the part of a file already downloaded
+fn part_size(file: &FileInfo) -> u64 {
+    fs::metadata(format!("{}.part", file.path))
+        .map(|m| m.len())
+        .unwrap_or(0)
+}
+
+/// Read `Content-Range` like `bytes 200-999/1000` or `bytes */1000`,
+/// return the first byte and the total size
+fn content_range(response: &Response) -> (Option<u64>, Option<u64>) {
+    let range = response
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.trim().strip_prefix("bytes "))
+        .and_then(|v| v.split_once('/'));
+
+    match range {
+        Some((bytes, total)) => (
+            bytes
+                .split_once('-')
+                .and_then(|(start, _)| start.parse().ok()),
+            total.parse().ok(),
+        ),
+        None => (None, None),
+    }
+}
 
-        file.write(&chunk)
-            .map_err(|_| "Error while writing to file".to_string())
-            .unwrap();
+/// Check the hash of a complete part, hashed again when `hasher` is
+/// `None`, then move it to the path of the file
+fn finish_part(file: &FileInfo, part: &str, hasher: Option<Sha1>) -> Result<(), error::Error> {
+    if let Some(expected) = &file.sha1 {
+        let found = match hasher {
+            Some(hasher) => hex(&hasher.finalize()),
+            None => file_sha1(part)?,
+        };
+        if !found.eq_ignore_ascii_case(expected) {
+            fs::remove_file(part)?;
+            return Err(error::Error::HashMismatch {
+                path: file.path.clone(),
+                expected: expected.clone(),
+                found,
+            });
+        }
     }
 
+    fs::rename(part, &file.path)?;
     Ok(())
 }
+
+/// Read `Retry-After`, in seconds or as a http date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Result of the download of one file
+#[derive(Debug)]
+pub struct FileDownload {
+    pub file: FileInfo,
+    pub result: Result<(), error::Error>,
+}
+
+/// Progress of downloads, send on the channel given to `Downloader::with_progress`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadEvent {
+    /// Send by `download_all` before the first download
+    Started {
+        total_files: usize,
+        total_bytes: u64,
+    },
+
+    /// A file starts downloading
+    FileStarted(FileInfo),
+
+    /// A download failed and is tried again after `delay`
+    FileRetry {
+        file: FileInfo,
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+
+    /// Bytes received for a file, `downloaded_bytes`
+    /// is the total of all files since the start
+    Progress {
+        path: String,
+        bytes: u64,
+        downloaded_bytes: u64,
+    },
+
+    FileFinished(FileInfo),
+    FileFailed {
+        file: FileInfo,
+        error: String,
+    },
+
+    /// Send by `download_all` when all files are done
+    Finished {
+        files: usize,
+        failed: usize,
+        downloaded_bytes: u64,
+    },
+}
+
+/// Download files with one http client, many files
+/// are downloaded at the same time
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    client: Client,
+    concurrency: usize,
+    retry: RetryPolicy,
+    mirrors: MirrorConfig,
+    cancel: CancellationToken,
+    rate_limit: RateLimit,
+    progress: Option<UnboundedSender<DownloadEvent>>,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Downloader {
+    pub fn new() -> Self {
+        Self::with_client(default_client())
+    }
+
+    /// Use this client for all downloads
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            client,
+            concurrency: DEFAULT_CONCURRENCY,
+            retry: RetryPolicy::default(),
+            mirrors: MirrorConfig::default(),
+            cancel: CancellationToken::new(),
+            rate_limit: RateLimit::default(),
+            progress: None,
+        }
+    }
+
+    /// Change the number of files downloaded at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Download files from these mirrors, the next one is
+    /// tried when a download failed on a mirror
+    pub fn with_mirrors(mut self, mirrors: MirrorConfig) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Stop all downloads when this token is cancelled, they return
+    /// `Error::Cancelled` and their partial files are removed
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Share this bandwidth limit between all downloads
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    /// Bandwidth limit of this downloader, it can be changed with `set_limit`
+    pub fn rate_limit(&self) -> &RateLimit {
+        &self.rate_limit
+    }
+
+    /// Token stopping the downloads of this downloader
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    /// Send progress events on this channel
+    pub fn with_progress(mut self, progress: UnboundedSender<DownloadEvent>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Send a progress event, the receiver may not listen anymore
+    fn notify(&self, event: DownloadEvent) {
+        if let Some(progress) = &self.progress {
+            let _ = progress.send(event);
+        }
+    }
+
+    /// Download one file, parent folders are created if needed
+    pub async fn download(&self, file: &FileInfo) -> Result<(), error::Error> {
+        self.intern_download(file, &AtomicU64::new(0)).await
+    }
+
+    /// Download a file and send its progress, `downloaded`
+    /// is the number of bytes received for all files
+    async fn intern_download(
+        &self,
+        file: &FileInfo,
+        downloaded: &AtomicU64,
+    ) -> Result<(), error::Error> {
+        self.notify(DownloadEvent::FileStarted(file.clone()));
+
+        // Bytes of this file counted in `downloaded`
+        let mut counted = 0;
+        let mut result = Err(format!("No url to download {}", file.path).into());
+        for url in self.mirrors.urls(&file.url) {
+            result = tokio::select! {
+                biased;
+                _ = self.cancel.cancelled() => Err(error::Error::Cancelled),
+                result = self.download_from(file, &url, downloaded, &mut counted) => result,
+            };
+
+            match &result {
+                Ok(_) | Err(error::Error::Cancelled) => break,
+                Err(err) => warn!("Download of {} failed: {}", url, err),
+            }
+        }
+
+        if let Err(error::Error::Cancelled) = result {
+            let part = format!("{}.part", file.path);
+            if let Err(err) = fs::remove_file(&part) {
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!("Can't remove {}: {}", part, err);
+                }
+            }
+            self.recount(file, downloaded, &mut counted, 0);
+        }
+
+        match &result {
+            Ok(_) => self.notify(DownloadEvent::FileFinished(file.clone())),
+            Err(err) => self.notify(DownloadEvent::FileFailed {
+                file: file.clone(),
+                error: err.to_string(),
+            }),
+        }
+
+        result
+    }
+
+    /// Download a file from one url, retried with the retry policy
+    async fn download_from(
+        &self,
+        file: &FileInfo,
+        url: &str,
+        downloaded: &AtomicU64,
+        counted: &mut u64,
+    ) -> Result<(), error::Error> {
+        let mut attempt = 0;
+        loop {
+            let error = match self.write_file(file, url, downloaded, counted).await {
+                Ok(_) => return Ok(()),
+                Err(err) => err,
+            };
+
+            // The part can be removed on errors, only what is left is counted
+            self.recount(file, downloaded, counted, part_size(file));
+            if attempt >= self.retry.max_retries || !is_retryable(&error) {
+                return Err(error);
+            }
+
+            attempt += 1;
+            // Retry-After is only used for 429 and 503, never above max_delay
+            let delay = match &error {
+                error::Error::Http {
+                    status: 429 | 503,
+                    retry_after: Some(delay),
+                    ..
+                } => (*delay).min(self.retry.max_delay),
+                _ => self.retry.delay(attempt),
+            };
+
+            self.notify(DownloadEvent::FileRetry {
+                file: file.clone(),
+                attempt,
+                delay,
+                error: error.to_string(),
+            });
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Download on `{path}.part`, renamed to `path` once the file is
+    /// complete and verified. If a part is already there the download
+    /// continue where it stopped when the server accepts ranges
+    async fn write_file(
+        &self,
+        file: &FileInfo,
+        url: &str,
+        downloaded: &AtomicU64,
+        counted: &mut u64,
+    ) -> Result<(), error::Error> {
+        let part = format!("{}.part", file.path);
+        if let Some(parent) = Path::new(&file.path).parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut existing = part_size(file);
+        if file.size > 0 && existing >= file.size {
+            if existing == file.size {
+                self.recount(file, downloaded, counted, existing);
+                return finish_part(file, &part, None);
+            }
+            fs::remove_file(&part)?;
+            existing = 0;
+        }
+        self.recount(file, downloaded, counted, existing);
+
+        let mut request = self.client.get(url);
+        if existing > 0 {
+            request = request.header(RANGE, format!("bytes={}-", existing));
+        }
+        let response = request.send().await?;
+
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            // The part can already be complete when the size isn't known
+            if existing > 0 && content_range(&response).1 == Some(existing) {
+                return finish_part(file, &part, None);
+            }
+            fs::remove_file(&part)?;
+            return Err(format!("Can't resume {}, the download will restart", file.path).into());
+        }
+
+        if !response.status().is_success() {
+            return Err(error::Error::Http {
+                url: url.to_string(),
+                status: response.status().as_u16(),
+                retry_after: retry_after(&response),
+            });
+        }
+
+        let mut hasher = Sha1::new();
+        let mut out = if existing > 0 && response.status() == StatusCode::PARTIAL_CONTENT {
+            if content_range(&response).0 != Some(existing) {
+                fs::remove_file(&part)?;
+                return Err(format!(
+                    "Wrong range received for {}, the download will restart",
+                    file.path
+                )
+                .into());
+            }
+            io::copy(&mut fs::File::open(&part)?, &mut hasher)?;
+            OpenOptions::new().append(true).open(&part).await?
+        } else {
+            self.recount(file, downloaded, counted, 0);
+            File::create(&part).await?
+        };
+
+        // Always flush to keep all received bytes for the next try
+        let result = self
+            .write_stream(file, response, &mut out, &mut hasher, downloaded, counted)
+            .await;
+        out.flush().await?;
+        result?;
+        drop(out);
+
+        finish_part(file, &part, Some(hasher))
+    }
+
+    async fn write_stream(
+        &self,
+        file: &FileInfo,
+        response: Response,
+        out: &mut File,
+        hasher: &mut Sha1,
+        downloaded: &AtomicU64,
+        counted: &mut u64,
+    ) -> Result<(), error::Error> {
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+            out.write_all(&chunk).await?;
+            hasher.update(&chunk);
+
+            let bytes = chunk.len() as u64;
+            self.recount(file, downloaded, counted, *counted + bytes);
+            self.rate_limit.consume(bytes).await;
+        }
+
+        Ok(())
+    }
+
+    /// Change the bytes of `file` counted in `downloaded` to `bytes`, the
+    /// bytes of a part are counted when it's reused and removed when
+    /// it's deleted so the total never counts the same bytes twice
+    fn recount(&self, file: &FileInfo, downloaded: &AtomicU64, counted: &mut u64, bytes: u64) {
+        if bytes > *counted {
+            let added = bytes - *counted;
+            self.notify(DownloadEvent::Progress {
+                path: file.path.clone(),
+                bytes: added,
+                downloaded_bytes: downloaded.fetch_add(added, Ordering::Relaxed) + added,
+            });
+        } else {
+            downloaded.fetch_sub(*counted - bytes, Ordering::Relaxed);
+        }
+        *counted = bytes;
+    }
+
+    /// Download all files, a failed download doesn't stop the others.
+    /// Results are in the order downloads are finished
+    pub async fn download_all(&self, files: Vec<FileInfo>) -> Vec<FileDownload> {
+        self.notify(DownloadEvent::Started {
+            total_files: files.len(),
+            total_bytes: files.iter().sum(),
+        });
+
+        let downloaded = AtomicU64::new(0);
+        let results: Vec<FileDownload> = stream::iter(files)
+            .map(|file| {
+                let downloaded = &downloaded;
+                async move {
+                    let result = self.intern_download(&file, downloaded).await;
+                    FileDownload { file, result }
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        self.notify(DownloadEvent::Finished {
+            files: results.len(),
+            failed: results.iter().filter(|d| d.result.is_err()).count(),
+            downloaded_bytes: downloaded.load(Ordering::Relaxed),
+        });
+
+        results
+    }
+}
+
+pub async fn download_file(
+    app: &MinecraftAuth,
+    url: String,
+    path: String,
+) -> Result<(), error::Error> {
+    app.downloader()
+        .download(&FileInfo::new(url, path, 0))
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mirror::Mirror,
+        test_server::{serve, Response},
+    };
+    use std::{
+        fs,
+        sync::{atomic::AtomicUsize, Arc},
+    };
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    #[tokio::test]
+    async fn download_many_files() {
+        let url = serve(|req| Response::new(200, req.path.trim_start_matches('/'))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap();
+
+        let mut files: Vec<FileInfo> = (0..20)
+            .map(|i| {
+                FileInfo::new(
+                    format!("{}/file{}", url, i),
+                    format!("{}/files/{}/file", path, i),
+                    5,
+                )
+            })
+            .collect();
+        files.push(FileInfo::new(
+            "http://127.0.0.1:1/closed".into(),
+            format!("{}/closed", path),
+            0,
+        ));
+
+        let (sender, mut receiver) = unbounded_channel();
+        let results = Downloader::new()
+            .with_retry(RetryPolicy::none())
+            .with_concurrency(4)
+            .with_progress(sender)
+            .download_all(files)
+            .await;
+        assert_eq!(results.len(), 21);
+
+        let mut events = vec![];
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(
+            events.first(),
+            Some(&DownloadEvent::Started {
+                total_files: 21,
+                total_bytes: 100
+            })
+        );
+        assert_eq!(
+            events.last(),
+            Some(&DownloadEvent::Finished {
+                files: 21,
+                failed: 1,
+                downloaded_bytes: (0..20).map(|i| format!("file{}", i).len() as u64).sum(),
+            })
+        );
+        let finished = events
+            .iter()
+            .filter(|e| matches!(e, DownloadEvent::FileFinished(_)))
+            .count();
+        assert_eq!(finished, 20);
+        assert!(events.iter().any(
+            |e| matches!(e, DownloadEvent::FileFailed { file, .. } if file.url.ends_with("closed"))
+        ));
+
+        for download in results {
+            if download.file.url.ends_with("closed") {
+                assert!(download.result.is_err());
+            } else {
+                download.result.unwrap();
+                let name = download.file.url.rsplit('/').next().unwrap();
+                assert_eq!(fs::read_to_string(download.file.path).unwrap(), name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sha1_mismatch() {
+        let url = serve(|_| Response::new(200, "content")).await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = format!("{}/file", dir.path().to_str().unwrap());
+        let sha1 = "040f06fd774092478d450774f5ba30c5da78acc8".to_string();
+
+        let file = FileInfo::new(url.clone(), path.clone(), 7).with_sha1(sha1.clone());
+        Downloader::new().download(&file).await.unwrap();
+        assert!(!file.needs_download(true));
+
+        fs::write(&path, "CONTENT").unwrap();
+        assert!(!file.needs_download(false));
+        assert!(file.needs_download(true));
+
+        let file = FileInfo::new(url, path.clone(), 7).with_sha1("0".repeat(40));
+        let err = Downloader::new()
+            .with_retry(RetryPolicy::none())
+            .download(&file)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, error::Error::HashMismatch { found, .. } if found == sha1));
+        assert!(!Path::new(&format!("{}.part", path)).exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "CONTENT");
+    }
+
+    #[tokio::test]
+    async fn retry_server_errors() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        let url = serve(move |req| {
+            if req.path == "/missing" {
+                return Response::new(404, "Not found");
+            }
+
+            match count.fetch_add(1, Ordering::SeqCst) {
+                0 => Response::new(503, "Unavailable").header("Retry-After", "86400"),
+                1 => Response::new(500, "Error").header("Retry-After", "0"),
+                _ => Response::new(200, "content"),
+            }
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = format!("{}/file", dir.path().to_str().unwrap());
+        let (sender, mut receiver) = unbounded_channel();
+        let downloader = Downloader::new()
+            .with_retry(RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+            })
+            .with_progress(sender);
+
+        let file = FileInfo::new(format!("{}/file", url), path.clone(), 7);
+        downloader.download(&file).await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "content");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let mut delays = vec![];
+        while let Ok(event) = receiver.try_recv() {
+            if let DownloadEvent::FileRetry { delay, .. } = event {
+                delays.push(delay);
+            }
+        }
+        assert_eq!(delays.len(), 2);
+        assert_eq!(delays[0], Duration::from_millis(10));
+        assert!(delays[1] >= Duration::from_millis(1));
+
+        let missing = FileInfo::new(format!("{}/missing", url), format!("{}.2", path), 0);
+        let err = downloader.download(&missing).await.unwrap_err();
+        assert!(matches!(err, error::Error::Http { status: 404, .. }));
+        assert!(!Path::new(&format!("{}.2.part", path)).exists());
+    }
+
+    #[tokio::test]
+    async fn resume_after_dropped_connection() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let body = content.clone();
+        let url = serve(move |req| match req.header("range") {
+            Some(range) => {
+                let start: usize = range
+                    .trim_start_matches("bytes=")
+                    .trim_end_matches('-')
+                    .parse()
+                    .unwrap();
+                Response::new(206, body[start..].to_vec()).header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, body.len() - 1, body.len()),
+                )
+            }
+            None => Response::new(200, body.clone()).cut_after(40_000),
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = format!("{}/client.jar", dir.path().to_str().unwrap());
+        let part = format!("{}.part", path);
+        let file = FileInfo::new(url, path.clone(), content.len() as u64);
+
+        let downloader = Downloader::new().with_retry(RetryPolicy::none());
+        assert!(downloader.download(&file).await.is_err());
+        assert!(!Path::new(&path).exists());
+        assert_eq!(fs::metadata(&part).unwrap().len(), 40_000);
+
+        // Bytes of the part are counted, the progress reaches the size
+        let file = file.with_sha1(hex(&Sha1::digest(&content)));
+        let (sender, receiver) = unbounded_channel();
+        let downloads = downloader
+            .with_progress(sender)
+            .download_all(vec![file])
+            .await;
+        assert!(downloads[0].result.is_ok());
+        assert_eq!(fs::read(&path).unwrap(), content);
+        assert!(!Path::new(&part).exists());
+        assert_eq!(finished_bytes(receiver), content.len() as u64);
+    }
+
+    fn finished_bytes(mut receiver: UnboundedReceiver<DownloadEvent>) -> u64 {
+        let mut finished = None;
+        while let Ok(event) = receiver.try_recv() {
+            if let DownloadEvent::Finished {
+                downloaded_bytes, ..
+            } = event
+            {
+                finished = Some(downloaded_bytes);
+            }
+        }
+        finished.unwrap()
+    }
+
+    #[tokio::test]
+    async fn complete_or_wrong_parts() {
+        let content = b"0123456789".to_vec();
+        let body = content.clone();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        let url = serve(move |req| {
+            count.fetch_add(1, Ordering::SeqCst);
+            match (req.path.as_str(), req.header("range")) {
+                ("/complete", Some(_)) => {
+                    Response::new(416, "").header("Content-Range", "bytes */10")
+                }
+                ("/shifted", Some(_)) => {
+                    Response::new(206, body[2..].to_vec()).header("Content-Range", "bytes 2-9/10")
+                }
+                _ => Response::new(200, body.clone()),
+            }
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path().to_str().unwrap();
+        let sha1 = hex(&Sha1::digest(&content));
+        let downloader = Downloader::new().with_retry(RetryPolicy::none());
+
+        // Size known: the complete part is only verified
+        let path = format!("{}/known", dir);
+        fs::write(format!("{}.part", path), &content).unwrap();
+        let file =
+            FileInfo::new(format!("{}/known", url), path.clone(), 10).with_sha1(sha1.clone());
+        downloader.download(&file).await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), content);
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+
+        // Size unknown: the server answers 416 with the total size
+        let path = format!("{}/complete", dir);
+        fs::write(format!("{}.part", path), &content).unwrap();
+        let file = FileInfo::new(format!("{}/complete", url), path.clone(), 0).with_sha1(sha1);
+        downloader.download(&file).await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), content);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // A range not starting at the end of the part is never appended
+        let path = format!("{}/shifted", dir);
+        let part = format!("{}.part", path);
+        fs::write(&part, &content[..5]).unwrap();
+        let file = FileInfo::new(format!("{}/shifted", url), path.clone(), 10);
+        assert!(downloader.download(&file).await.is_err());
+        assert!(!Path::new(&part).exists());
+
+        // The restarted download doesn't count the removed part
+        fs::write(&part, &content[..5]).unwrap();
+        let (sender, receiver) = unbounded_channel();
+        let retry = RetryPolicy {
+            max_retries: 1,
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        };
+        let downloads = Downloader::new()
+            .with_retry(retry)
+            .with_progress(sender)
+            .download_all(vec![file])
+            .await;
+        assert!(downloads[0].result.is_ok());
+        assert_eq!(fs::read(&path).unwrap(), content);
+        assert_eq!(finished_bytes(receiver), 10);
+    }
+
+    #[tokio::test]
+    async fn mirror_fallback() {
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let paths = requests.clone();
+        let url = serve(move |req| {
+            paths.lock().unwrap().push(req.path.clone());
+            match req.path.as_str() {
+                "/good/lib.jar" => Response::new(200, "mirror"),
+                _ => Response::new(404, "Not found"),
+            }
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = format!("{}/lib.jar", dir.path().to_str().unwrap());
+        let origin = "https://libraries.minecraft.net/lib.jar";
+        let mirrors = MirrorConfig::new(vec![
+            Mirror::new("broken").rewrite(
+                "https://libraries.minecraft.net",
+                &format!("{}/broken", url),
+            ),
+            Mirror::new("good").rewrite("libraries.minecraft.net", &format!("{}/good", url)),
+        ]);
+
+        let file = FileInfo::new(origin.into(), path.clone(), 6);
+        let downloader = Downloader::new().with_mirrors(mirrors.clone());
+        downloader.download(&file).await.unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "mirror");
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec!["/broken/lib.jar", "/good/lib.jar"]
+        );
+
+        let missing = FileInfo::new(format!("{}/missing", url), format!("{}.2", path), 0);
+        let mirrors = MirrorConfig {
+            fallback_to_origin: false,
+            ..mirrors
+        };
+        let err = Downloader::new()
+            .with_mirrors(mirrors)
+            .download(&missing)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No url"));
+    }
+
+    #[tokio::test]
+    async fn cancel_downloads() {
+        let url = serve(|req| match req.path.as_str() {
+            "/slow" => Response::new(200, vec![0; 100_000]).hang_after(1_000),
+            _ => Response::new(200, "content"),
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = format!("{}/slow", dir.path().to_str().unwrap());
+        let cancel = CancellationToken::new();
+        let (sender, mut receiver) = unbounded_channel();
+        let downloader = Downloader::new()
+            .with_cancellation(cancel.clone())
+            .with_progress(sender);
+
+        let file = FileInfo::new(format!("{}/slow", url), path.clone(), 100_000);
+        let download = tokio::spawn({
+            let downloader = downloader.clone();
+            async move { downloader.download(&file).await }
+        });
+
+        while let Some(event) = receiver.recv().await {
+            if let DownloadEvent::Progress { .. } = event {
+                break;
+            }
+        }
+        assert!(Path::new(&format!("{}.part", path)).exists());
+
+        cancel.cancel();
+        let err = download.await.unwrap().unwrap_err();
+        assert!(matches!(err, error::Error::Cancelled));
+
+        let files = (0..3)
+            .map(|i| FileInfo::new(format!("{}/file{}", url, i), format!("{}{}", path, i), 7))
+            .collect();
+        let downloads = downloader.download_all(files).await;
+        assert!(downloads
+            .iter()
+            .all(|d| matches!(d.result, Err(error::Error::Cancelled))));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn shared_rate_limit() {
+        let url = serve(|_| Response::new(200, vec![0; 25_000])).await;
+        let dir = tempfile::tempdir().unwrap();
+        let files = |name: &str| -> Vec<FileInfo> {
+            (0..3)
+                .map(|i| {
+                    let path = format!("{}/{}{}", dir.path().to_str().unwrap(), name, i);
+                    FileInfo::new(format!("{}/{}", url, i), path, 25_000)
+                })
+                .collect()
+        };
+
+        let downloader = Downloader::new().with_rate_limit(RateLimit::new(Some(50_000)));
+        let start = Instant::now();
+        let downloads = downloader.download_all(files("limited")).await;
+        assert!(downloads.iter().all(|d| d.result.is_ok()));
+        assert!(start.elapsed() >= Duration::from_millis(1_200));
+
+        downloader.rate_limit().set_limit(None);
+        let start = Instant::now();
+        let downloads = downloader.download_all(files("free")).await;
+        assert!(downloads.iter().all(|d| d.result.is_ok()));
+        assert!(start.elapsed() < Duration::from_millis(1_000));
+    }
+}
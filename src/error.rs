@@ -1,5 +1,9 @@
-use crate::{instance::InstanceCreateError, user::UCStatus};
-use std::io;
+use crate::{
+    account::AccountStoreError,
+    instance::InstanceCreateError,
+    user::{AuthError, UCStatus},
+};
+use std::{io, time::Duration};
 use tokio::sync::mpsc::error::SendError;
 use zip::result::ZipError;
 
@@ -8,9 +12,41 @@ pub enum Error {
     File(io::Error),
     Reqwest(reqwest::Error),
     SerdeJson(serde_json::Error),
-    SendError(SendError<UCStatus>),
+    SendError(Box<SendError<UCStatus>>),
     InstanceCreate(InstanceCreateError),
+    AccountStore(AccountStoreError),
+    Auth(AuthError),
     Zip(ZipError),
+
+    /// The server answered with an error status
+    Http {
+        url: String,
+        status: u16,
+
+        /// Delay asked by the server before trying again
+        retry_after: Option<Duration>,
+    },
+
+    /// An api answered with an error status, `message` is taken from
+    /// the error fields of the json body when there is one
+    Api {
+        status: u16,
+        message: String,
+        body: serde_json::Value,
+    },
+
+    /// The server refused the credentials or the token of an account
+    Rejected(String),
+
+    /// A downloaded file doesn't have the expected hash
+    HashMismatch {
+        path: String,
+        expected: String,
+        found: String,
+    },
+
+    /// Stopped with a cancellation token
+    Cancelled,
     Other(String),
 }
 
@@ -22,7 +58,25 @@ impl std::fmt::Display for Error {
             Error::SerdeJson(s) => s.to_string(),
             Error::SendError(s) => s.to_string(),
             Error::InstanceCreate(ic) => ic.to_string(),
+            Error::AccountStore(a) => a.to_string(),
+            Error::Auth(a) => a.to_string(),
             Error::Zip(z) => z.to_string(),
+            Error::Http { url, status, .. } => {
+                format!("Request to {} failed with status {}", url, status)
+            }
+            Error::Api {
+                status, message, ..
+            } => format!("Request failed ({}): {}", status, message),
+            Error::Rejected(r) => r.to_string(),
+            Error::HashMismatch {
+                path,
+                expected,
+                found,
+            } => format!(
+                "Wrong hash for {}: expected {}, found {}",
+                path, expected, found
+            ),
+            Error::Cancelled => "Cancelled".to_string(),
             Error::Other(o) => o.to_string(),
         };
 
@@ -30,6 +84,19 @@ impl std::fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// Turn a client error of an authentication api into `Rejected`,
+    /// server errors are kept as they are
+    pub(crate) fn rejected(self) -> Self {
+        match self {
+            Error::Api {
+                status, message, ..
+            } if (400..500).contains(&status) => Error::Rejected(message),
+            err => err,
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Self::File(error)
@@ -62,7 +129,7 @@ impl From<serde_json::Error> for Error {
 
 impl From<SendError<UCStatus>> for Error {
     fn from(error: SendError<UCStatus>) -> Self {
-        Self::SendError(error)
+        Self::SendError(Box::new(error))
     }
 }
 
@@ -72,8 +139,20 @@ impl From<InstanceCreateError> for Error {
     }
 }
 
+impl From<AccountStoreError> for Error {
+    fn from(error: AccountStoreError) -> Self {
+        Self::AccountStore(error)
+    }
+}
+
 impl From<ZipError> for Error {
     fn from(error: ZipError) -> Self {
         Self::Zip(error)
     }
 }
+
+impl From<AuthError> for Error {
+    fn from(error: AuthError) -> Self {
+        Self::Auth(error)
+    }
+}
@@ -1,10 +1,11 @@
 use crate::{
+    authlib_injector::{self, AuthlibInjector},
     data::{download::Classifier, package::Package},
     downloader::FileInfo,
     error::{self, Error},
     java::find_java_version,
     native::os_native_name,
-    user::User,
+    user::{User, UserType},
     utils::scan,
     version::manifest,
     MinecraftAuth,
@@ -23,6 +24,9 @@ use std::{
 };
 use zip::ZipArchive;
 
+/// Access token given to the game for offline users
+const OFFLINE_ACCESS_TOKEN: &str = "0";
+
 #[derive(Debug)]
 pub enum InstanceCreateError {
     NoFoundVersion,
@@ -31,6 +35,9 @@ pub enum InstanceCreateError {
     ReadConfigError(String),
     NoFoundManifestVersion,
     NeedDownload(Vec<FileInfo>),
+
+    /// The instance can't be started in demo mode
+    GameNotOwned(String),
 }
 
 impl Display for InstanceCreateError {
@@ -42,6 +49,9 @@ impl Display for InstanceCreateError {
             InstanceCreateError::ReadConfigError(config) => config.clone(),
             InstanceCreateError::NoFoundManifestVersion => String::from("No found manifest"),
             InstanceCreateError::NeedDownload(v) => format!("Need {} Downloads", v.len()),
+            InstanceCreateError::GameNotOwned(user) => {
+                format!("{} doesn't own minecraft, only the demo is available", user)
+            }
         };
 
         f.write_str(&message)
@@ -231,10 +241,7 @@ impl Instance {
         );
         self.add_param(
             "javaVersion",
-            Param::new(
-                DataParam::Int(manifest.java_version.major_version as i32),
-                false,
-            ),
+            Param::new(DataParam::Int(manifest.java_version.major_version), false),
         );
         self.add_param(
             "assetIndex",
@@ -301,6 +308,16 @@ impl Instance {
         Ok(())
     }
 
+    /// Refuse to start this instance with accounts
+    /// that can only play the demo
+    pub fn require_ownership(&mut self, require: bool) -> Result<(), error::Error> {
+        self.add_param(
+            "requireOwnership",
+            Param::new(DataParam::Str(require.to_string()), true),
+        );
+        self.save_config()
+    }
+
     pub fn add_param(&mut self, name: &str, val: Param) -> Option<Param> {
         self.param.insert(name.to_string(), val)
     }
@@ -344,9 +361,15 @@ impl Instance {
             "--username".to_string(),
             user.username.clone(),
             "--accessToken".to_string(),
-            user.access_token.clone(),
+            if user.user_type == UserType::Legacy {
+                OFFLINE_ACCESS_TOKEN.to_string()
+            } else {
+                user.access_token.clone()
+            },
             "--uuid".to_string(),
             user.uuid.clone(),
+            "--userType".to_string(),
+            user.user_type.as_str().to_string(),
             "--version".to_string(),
             self.param("version").to_string(),
             "--gameDir".to_string(),
@@ -357,6 +380,15 @@ impl Instance {
             self.param("assetIndex").to_string(),
         ];
 
+        if let Some(injector) = AuthlibInjector::from_cache(app, user) {
+            let cp = v.iter().position(|a| a == "-cp").unwrap_or_default();
+            v.splice(cp..cp, injector.jvm_args());
+        }
+
+        if user.is_demo() {
+            v.push("--demo".into());
+        }
+
         if self.param("useForge").is_true() {
             v.append(&mut vec![
                 "--tweakClass".into(),
@@ -484,12 +516,20 @@ fn java_is_command() -> bool {
 }
 
 // Find better java version for version
-/// Start minecraft instance and return a child process
-pub fn start_instance(
+/// Start minecraft instance and return a child process, accounts
+/// without the game start in demo mode. authlib-injector is downloaded if the user is connected
+/// to a third-party yggdrasil server
+pub async fn start_instance(
     app: &MinecraftAuth,
     user: &User,
     i: &Instance,
 ) -> Result<Child, error::Error> {
+    if user.is_demo() && i.param("requireOwnership").is_true() {
+        return Err(InstanceCreateError::GameNotOwned(user.username.clone()).into());
+    }
+
+    authlib_injector::prepare(app, user).await?;
+
     if let DataParam::Int(version) = i.param("javaVersion") {
         let current_dir = env::current_dir()?;
 
@@ -525,3 +565,31 @@ pub fn start_instance(
         Err("No found javaVersion param on Instance".into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn demo_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+        let mut user = User::offline("Player".into());
+        let mut instance = Instance::default();
+        instance.add_param(
+            "path",
+            Param::new(DataParam::Str(dir.path().to_str().unwrap().into()), false),
+        );
+
+        assert!(!instance.args(&app, &user).contains(&"--demo".to_string()));
+
+        user.owns_game = Some(false);
+        assert!(instance.args(&app, &user).contains(&"--demo".to_string()));
+
+        instance.require_ownership(true).unwrap();
+        assert!(matches!(
+            start_instance(&app, &user, &instance).await,
+            Err(Error::InstanceCreate(InstanceCreateError::GameNotOwned(_)))
+        ));
+    }
+}
@@ -1,24 +1,173 @@
-use std::fs;
+use downloader::{Downloader, RateLimit};
+use error::Error;
+use mirror::MirrorConfig;
+use network::NetworkConfig;
+use provider::Provider;
+use reqwest::Client;
+use secret::{PlaintextBackend, SecretBackend};
+use serde::{Deserialize, Serialize};
+use std::{fs, sync::Arc};
+use user::User;
+use version::ManifestRefresh;
 
+pub mod account;
+pub mod authlib_injector;
+pub mod certificates;
 pub mod data;
 pub mod downloader;
 pub mod error;
 pub mod instance;
 pub mod java;
+pub mod lookup;
+pub mod microsoft;
+pub mod mirror;
 pub mod native;
+pub mod network;
+pub mod profile;
+pub mod provider;
+pub mod secret;
+pub mod session;
+#[cfg(test)]
+mod test_server;
 pub mod user;
 pub mod utils;
 pub mod version;
 
+/// Base urls of mojang apis, change them to use another server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiUrls {
+    /// Name to uuid lookups
+    pub api: String,
+
+    /// Profiles with textures and server sessions
+    pub session_server: String,
+
+    /// Skins, capes and certificates of an account
+    pub services: String,
+}
+
+impl Default for ApiUrls {
+    fn default() -> Self {
+        Self {
+            api: "https://api.mojang.com".into(),
+            session_server: "https://sessionserver.mojang.com".into(),
+            services: "https://api.minecraftservices.com".into(),
+        }
+    }
+}
+
+impl ApiUrls {
+    /// Session server used by the account, third-party yggdrasil
+    /// servers have it under `{api_root}/sessionserver`
+    pub fn session_server_for(&self, user: &User) -> String {
+        match &user.provider {
+            Provider::Yggdrasil { url } => format!("{}/sessionserver", url),
+            _ => self.session_server.clone(),
+        }
+    }
+
+    /// Minecraft services of the account, third-party yggdrasil
+    /// servers have it under `{api_root}/minecraftservices`
+    pub fn services_for(&self, user: &User) -> Result<String, Error> {
+        match &user.provider {
+            Provider::Yggdrasil { url } => Ok(format!("{}/minecraftservices", url)),
+            Provider::Offline => Err("Offline accounts don't use minecraft services".into()),
+            _ => Ok(self.services.clone()),
+        }
+    }
+
+    /// Use the same base url for all apis
+    pub fn with_base_url(url: &str) -> Self {
+        let url = url.trim_end_matches('/').to_string();
+        Self {
+            api: url.clone(),
+            session_server: url.clone(),
+            services: url,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct MinecraftAuth {
     pub name: String,
     pub path: String,
+
+    /// Where account tokens are saved
+    pub secrets: Arc<dyn SecretBackend>,
+
+    pub api: ApiUrls,
+
+    /// Mirrors used to download versions
+    pub mirrors: MirrorConfig,
+
+    /// Bandwidth limit shared by all downloaders of the app
+    pub rate_limit: RateLimit,
+
+    /// When the list of versions is downloaded again
+    pub manifest_refresh: ManifestRefresh,
+
+    network: NetworkConfig,
+
+    /// Created from `network`, used by all requests
+    client: Client,
 }
 
 impl MinecraftAuth {
     pub fn new(name: String, path: String) -> Self {
-        Self { name, path }
+        Self {
+            name,
+            path,
+            secrets: Arc::new(PlaintextBackend),
+            api: ApiUrls::default(),
+            mirrors: MirrorConfig::default(),
+            rate_limit: RateLimit::default(),
+            manifest_refresh: ManifestRefresh::default(),
+            network: NetworkConfig::default(),
+            client: network::default_client(),
+        }
+    }
+
+    /// Use another backend to save account tokens
+    pub fn with_secret_backend(mut self, secrets: Arc<dyn SecretBackend>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    /// Download manifests, libraries, assets and clients from mirrors
+    pub fn with_mirrors(mut self, mirrors: MirrorConfig) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Limit the bandwidth of all downloads, in bytes per second.
+    /// `app.rate_limit.set_limit` changes it during downloads
+    pub fn with_rate_limit(mut self, bytes_per_sec: Option<u64>) -> Self {
+        self.rate_limit = RateLimit::new(bytes_per_sec);
+        self
+    }
+
+    /// Use a proxy, custom certificates, timeouts or User-Agent for all
+    /// requests. An error is returned if the proxy or a certificate is invalid
+    pub fn with_network(mut self, network: NetworkConfig) -> Result<Self, Error> {
+        self.client = network.client()?;
+        self.network = network;
+        Ok(self)
+    }
+
+    pub fn network(&self) -> &NetworkConfig {
+        &self.network
+    }
+
+    /// Http client created with the network config
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Downloader using the network config, mirrors and bandwidth limit of this app
+    pub fn downloader(&self) -> Downloader {
+        Downloader::with_client(self.client.clone())
+            .with_mirrors(self.mirrors.clone())
+            .with_rate_limit(self.rate_limit.clone())
     }
 
     /// Create MinecraftAuth with just a name, and get
@@ -29,6 +178,6 @@ impl MinecraftAuth {
         let path = format!("{}/{}", temp_path, name);
         fs::create_dir_all(path.clone()).ok()?;
 
-        Some(Self { name, path })
+        Some(Self::new(name, path))
     }
 }
@@ -0,0 +1,361 @@
+use crate::{
+    error::Error,
+    user::GameProfile,
+    utils::{json_response, now, write_atomic},
+    MinecraftAuth,
+};
+use log::warn;
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, sync::Mutex, time::Duration};
+
+/// Time to keep a lookup result by default
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Max number of names for one bulk request
+const BULK_SIZE: usize = 10;
+
+/// Remove dashes of an uuid, an error is returned if it's not a valid uuid
+pub fn undashed(uuid: &str) -> Result<String, Error> {
+    let id: String = uuid.chars().filter(|c| *c != '-').collect();
+    if id.len() != 32 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid uuid: {}", uuid).into());
+    }
+
+    Ok(id.to_lowercase())
+}
+
+/// Check a player name is made of 1 to 16 letters, digits or `_`,
+/// an error is returned else
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid = (1..=16).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("Invalid player name: {}", name).into());
+    }
+
+    Ok(())
+}
+
+/// Write an uuid with dashes, like `069a79f4-44e9-4726-a5be-fca90e38aaf5`
+pub fn dashed(uuid: &str) -> Result<String, Error> {
+    let id = undashed(uuid)?;
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    ))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    /// `None` if nobody use this name or uuid
+    profile: Option<GameProfile>,
+
+    /// Seconds since unix epoch
+    time: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LookupCache {
+    /// Key is the lowercase name
+    names: HashMap<String, CacheEntry>,
+
+    /// Key is the undashed uuid
+    uuids: HashMap<String, CacheEntry>,
+}
+
+/// Convert player names to uuids and back. Results are kept
+/// on `{app.path}/lookup_cache.json` to not hit rate limits
+#[derive(Debug)]
+pub struct Lookup {
+    app: MinecraftAuth,
+    ttl: Duration,
+    cache: Mutex<LookupCache>,
+}
+
+impl Lookup {
+    /// Create a lookup with the cache saved on disk
+    pub fn new(app: &MinecraftAuth) -> Self {
+        let cache = fs::read_to_string(Lookup::path(app))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+        Self {
+            app: app.clone(),
+            ttl: DEFAULT_TTL,
+            cache: Mutex::new(cache),
+        }
+    }
+
+    /// Change the time results are kept
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    fn path(app: &MinecraftAuth) -> String {
+        format!("{}/lookup_cache.json", app.path)
+    }
+
+    fn cached(&self, name: Option<&str>, uuid: Option<&str>) -> Option<Option<GameProfile>> {
+        let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = match (name, uuid) {
+            (Some(name), _) => cache.names.get(&name.to_lowercase()),
+            (_, Some(uuid)) => cache.uuids.get(uuid),
+            _ => None,
+        }?;
+
+        if now().saturating_sub(entry.time) < self.ttl.as_secs() {
+            Some(entry.profile.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Keep results of the names and uuids asked in memory and write
+    /// them on disk, expired results are removed at the same time
+    fn store(&self, names: &[&str], uuids: &[&str], profiles: &[GameProfile]) {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        let time = now();
+        let ttl = self.ttl.as_secs();
+        cache.names.retain(|_, e| time.saturating_sub(e.time) < ttl);
+        cache.uuids.retain(|_, e| time.saturating_sub(e.time) < ttl);
+
+        for name in names {
+            let profile = profiles
+                .iter()
+                .find(|p| p.name.eq_ignore_ascii_case(name))
+                .cloned();
+            cache
+                .names
+                .insert(name.to_lowercase(), CacheEntry { profile, time });
+        }
+
+        for uuid in uuids {
+            let profile = profiles.iter().find(|p| p.id == *uuid).cloned();
+            cache
+                .uuids
+                .insert(uuid.to_string(), CacheEntry { profile, time });
+        }
+
+        for profile in profiles {
+            let entry = CacheEntry {
+                profile: Some(profile.clone()),
+                time,
+            };
+            cache
+                .names
+                .insert(profile.name.to_lowercase(), entry.clone());
+            cache.uuids.insert(profile.id.clone(), entry);
+        }
+
+        let saved = fs::create_dir_all(&self.app.path)
+            .map_err(Error::from)
+            .and_then(|_| Ok(serde_json::to_string(&*cache)?))
+            .and_then(|content| Ok(write_atomic(Lookup::path(&self.app), content)?));
+        if let Err(err) = saved {
+            warn!("Can't save lookup cache: {}", err);
+        }
+    }
+
+    /// Return the profile, `None` if nobody is found
+    async fn optional_profile(response: Response) -> Result<Option<GameProfile>, Error> {
+        match response.status() {
+            StatusCode::NO_CONTENT | StatusCode::NOT_FOUND => Ok(None),
+            _ => Ok(Some(serde_json::from_value(
+                json_response(response).await?,
+            )?)),
+        }
+    }
+
+    /// Find the profile of a player with its name
+    pub async fn profile_by_name(&self, name: &str) -> Result<Option<GameProfile>, Error> {
+        check_name(name)?;
+        if let Some(profile) = self.cached(Some(name), None) {
+            return Ok(profile);
+        }
+
+        let response = self
+            .app
+            .client()
+            .get(format!(
+                "{}/users/profiles/minecraft/{}",
+                self.app.api.api, name
+            ))
+            .send()
+            .await?;
+
+        let profile = Lookup::optional_profile(response).await?;
+        self.store(&[name], &[], profile.as_slice());
+        Ok(profile)
+    }
+
+    /// Find the undashed uuid of a player with its name
+    pub async fn uuid(&self, name: &str) -> Result<Option<String>, Error> {
+        Ok(self.profile_by_name(name).await?.map(|p| p.id))
+    }
+
+    /// Find the profiles of many players, names are sent by groups
+    /// of 10. Names without player are not in the result
+    pub async fn profiles_by_names(&self, names: &[&str]) -> Result<Vec<GameProfile>, Error> {
+        let mut profiles = vec![];
+        let mut missing = vec![];
+        for name in names {
+            check_name(name)?;
+            match self.cached(Some(name), None) {
+                Some(profile) => profiles.extend(profile),
+                None => missing.push(*name),
+            }
+        }
+
+        let client = self.app.client();
+        for chunk in missing.chunks(BULK_SIZE) {
+            let response = client
+                .post(format!("{}/profiles/minecraft", self.app.api.api))
+                .json(chunk)
+                .send()
+                .await?;
+
+            let found: Vec<GameProfile> = serde_json::from_value(json_response(response).await?)?;
+            self.store(chunk, &[], &found);
+            profiles.extend(found);
+        }
+
+        Ok(profiles)
+    }
+
+    /// Find the uuids of many players, key is the name given
+    pub async fn uuids(&self, names: &[&str]) -> Result<HashMap<String, String>, Error> {
+        let profiles = self.profiles_by_names(names).await?;
+        Ok(names
+            .iter()
+            .filter_map(|name| {
+                let profile = profiles
+                    .iter()
+                    .find(|p| p.name.eq_ignore_ascii_case(name))?;
+                Some((name.to_string(), profile.id.clone()))
+            })
+            .collect())
+    }
+
+    /// Find the current name of a player with its uuid, dashed or not
+    pub async fn profile(&self, uuid: &str) -> Result<Option<GameProfile>, Error> {
+        let uuid = undashed(uuid)?;
+        if let Some(profile) = self.cached(None, Some(&uuid)) {
+            return Ok(profile);
+        }
+
+        let response = self
+            .app
+            .client()
+            .get(format!(
+                "{}/session/minecraft/profile/{}",
+                self.app.api.session_server, uuid
+            ))
+            .send()
+            .await?;
+
+        let profile = Lookup::optional_profile(response).await?;
+        self.store(&[], &[&uuid], profile.as_slice());
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_server::{serve, Response},
+        ApiUrls,
+    };
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    const NOTCH: &str = "069a79f444e94726a5befca90e38aaf5";
+
+    #[test]
+    fn uuid_format() {
+        let dashed_id = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+        assert_eq!(undashed(dashed_id).unwrap(), NOTCH);
+        assert_eq!(dashed(NOTCH).unwrap(), dashed_id);
+        assert_eq!(dashed(&dashed_id.to_uppercase()).unwrap(), dashed_id);
+        assert!(undashed("Notch").is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+        app.api = ApiUrls::with_base_url("http://127.0.0.1:1");
+        let lookup = Lookup::new(&app);
+
+        for name in ["", "a/b", "name?x=1", "name#", "ThisNameIsTooLong"] {
+            assert!(lookup.profile_by_name(name).await.is_err());
+        }
+        assert!(lookup.uuids(&["jeb_", "../x"]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cached_lookups() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let count = requests.clone();
+        let url = serve(move |req| {
+            count.fetch_add(1, Ordering::SeqCst);
+            match req.path.as_str() {
+                "/users/profiles/minecraft/notch" => {
+                    Response::json(200, json!({ "id": NOTCH, "name": "Notch" }))
+                }
+                "/profiles/minecraft" => {
+                    assert!(req.json().as_array().unwrap().len() <= BULK_SIZE);
+                    Response::json(200, json!([{ "id": "jeb", "name": "jeb_" }]))
+                }
+                path if path == format!("/session/minecraft/profile/{}", NOTCH) => {
+                    Response::json(200, json!({ "id": NOTCH, "name": "Notch" }))
+                }
+                _ => Response::new(204, ""),
+            }
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+        app.api = ApiUrls::with_base_url(&url);
+        let lookup = Lookup::new(&app);
+
+        assert_eq!(lookup.uuid("notch").await.unwrap().unwrap(), NOTCH);
+        assert_eq!(lookup.uuid("Nobody").await.unwrap(), None);
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let mut names: Vec<String> = (0..12).map(|i| format!("player{}", i)).collect();
+        names.push("jeb_".into());
+        names.push("Notch".into());
+        let names: Vec<&str> = names.iter().map(|n| n.as_str()).collect();
+        let uuids = lookup.uuids(&names).await.unwrap();
+        assert_eq!(uuids.len(), 2);
+        assert_eq!(uuids["Notch"], NOTCH);
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        let profile = lookup
+            .profile("069a79f4-44e9-4726-a5be-fca90e38aaf5")
+            .await
+            .unwrap();
+        assert_eq!(profile.unwrap().name, "Notch");
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        let lookup = Lookup::new(&app);
+        assert_eq!(lookup.uuid("JEB_").await.unwrap().unwrap(), "jeb");
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        let lookup = Lookup::new(&app).with_ttl(Duration::ZERO);
+        assert_eq!(lookup.uuid("notch").await.unwrap().unwrap(), NOTCH);
+        assert_eq!(requests.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,625 @@
+use crate::{
+    error::Error,
+    provider::Provider,
+    user::{offline_uuid, AuthEvent, GameProfile, User, UserType},
+    utils::json_response,
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Name used to play the demo with an account without profile
+const DEMO_USERNAME: &str = "Player";
+
+/// Configuration of the microsoft connection flow, all urls
+/// can be changed to use another server (like a local mock)
+#[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct MicrosoftConfig {
+    /// Azure application client id
+    pub client_id: String,
+
+    /// OAuth scope asked for the microsoft token
+    pub scope: String,
+
+    /// Base url of the microsoft identity platform
+    pub login_url: String,
+
+    /// Base url of the xbox live user authentication
+    pub xbox_live_url: String,
+
+    /// Base url of the xbox live security token service
+    pub xsts_url: String,
+
+    /// Base url of the minecraft services api
+    pub minecraft_url: String,
+}
+
+impl MicrosoftConfig {
+    pub fn new(client_id: String) -> Self {
+        Self {
+            client_id,
+            scope: "XboxLive.signin offline_access".into(),
+            login_url: "https://login.microsoftonline.com/consumers".into(),
+            xbox_live_url: "https://user.auth.xboxlive.com".into(),
+            xsts_url: "https://xsts.auth.xboxlive.com".into(),
+            minecraft_url: "https://api.minecraftservices.com".into(),
+        }
+    }
+
+    /// Use the same base url for every endpoint
+    pub fn with_base_url(client_id: String, url: &str) -> Self {
+        Self {
+            login_url: url.into(),
+            xbox_live_url: url.into(),
+            xsts_url: url.into(),
+            minecraft_url: url.into(),
+            ..Self::new(client_id)
+        }
+    }
+}
+
+/// Code the user need to enter on the verification uri
+/// to accept the connection
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct DeviceCode {
+    pub user_code: String,
+    pub verification_uri: String,
+
+    /// Message to show to the user, given by microsoft
+    pub message: String,
+
+    /// Time in seconds before the code expire
+    pub expires_in: u64,
+}
+
+/// Current step of the microsoft connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
+pub enum AuthStep {
+    MicrosoftToken,
+    XboxLive,
+    Xsts,
+    Minecraft,
+    Profile,
+}
+
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    #[serde(default)]
+    message: String,
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+/// Seconds between polls when the server doesn't give it, see RFC 8628
+fn default_interval() -> u64 {
+    5
+}
+
+/// Token return by the microsoft identity platform
+#[derive(Debug, Clone)]
+pub(crate) struct MsaToken {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+struct XboxToken {
+    token: String,
+    user_hash: String,
+}
+
+async fn request_device_code(
+    client: &Client,
+    config: &MicrosoftConfig,
+) -> Result<DeviceCodeResponse, Error> {
+    let response = client
+        .post(format!("{}/oauth2/v2.0/devicecode", config.login_url))
+        .form(&[
+            ("client_id", config.client_id.as_str()),
+            ("scope", config.scope.as_str()),
+        ])
+        .send()
+        .await?;
+
+    let data = json_response(response).await.map_err(Error::rejected)?;
+    Ok(serde_json::from_value(data)?)
+}
+
+fn msa_token_from(data: &Value) -> Result<MsaToken, Error> {
+    Ok(MsaToken {
+        access_token: data["access_token"]
+            .as_str()
+            .ok_or("access_token str")?
+            .to_string(),
+        refresh_token: data["refresh_token"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// Wait until the user accept the connection with the device code
+async fn poll_device_token(
+    client: &Client,
+    config: &MicrosoftConfig,
+    device: &DeviceCodeResponse,
+) -> Result<MsaToken, Error> {
+    let start = Instant::now();
+    let mut interval = device.interval;
+
+    loop {
+        if start.elapsed().as_secs() >= device.expires_in {
+            return Err(Error::Rejected("Device code expired".into()));
+        }
+
+        tokio::time::sleep(Duration::from_secs(interval)).await;
+        let response = client
+            .post(format!("{}/oauth2/v2.0/token", config.login_url))
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("client_id", config.client_id.as_str()),
+                ("device_code", device.device_code.as_str()),
+            ])
+            .send()
+            .await?;
+
+        match json_response(response).await {
+            Ok(data) => return msa_token_from(&data),
+            Err(Error::Api { body, .. }) if body["error"] == "authorization_pending" => {}
+            Err(Error::Api { body, .. }) if body["error"] == "slow_down" => interval += 5,
+            Err(err) => return Err(err.rejected()),
+        }
+    }
+}
+
+async fn xbox_live(
+    client: &Client,
+    config: &MicrosoftConfig,
+    msa: &MsaToken,
+) -> Result<XboxToken, Error> {
+    let body = json!({
+        "Properties": {
+            "AuthMethod": "RPS",
+            "SiteName": "user.auth.xboxlive.com",
+            "RpsTicket": format!("d={}", msa.access_token),
+        },
+        "RelyingParty": "http://auth.xboxlive.com",
+        "TokenType": "JWT",
+    });
+
+    let response = client
+        .post(format!("{}/user/authenticate", config.xbox_live_url))
+        .json(&body)
+        .send()
+        .await?;
+
+    xbox_token_from(json_response(response).await.map_err(Error::rejected)?)
+}
+
+async fn xsts(
+    client: &Client,
+    config: &MicrosoftConfig,
+    xbox: &XboxToken,
+) -> Result<XboxToken, Error> {
+    let body = json!({
+        "Properties": {
+            "SandboxId": "RETAIL",
+            "UserTokens": [xbox.token],
+        },
+        "RelyingParty": "rp://api.minecraftservices.com/",
+        "TokenType": "JWT",
+    });
+
+    let response = client
+        .post(format!("{}/xsts/authorize", config.xsts_url))
+        .json(&body)
+        .send()
+        .await?;
+
+    let data = match json_response(response).await {
+        Err(Error::Api { body, .. }) if body["XErr"].is_u64() => body,
+        result => result.map_err(Error::rejected)?,
+    };
+    if let Some(code) = data["XErr"].as_u64() {
+        let reason = match code {
+            2148916233 => "This account doesn't have an Xbox account",
+            2148916235 => "Xbox Live is not available in this country",
+            2148916236 | 2148916237 => "This account needs adult verification",
+            2148916238 => "This account is a child account and needs to be added to a family",
+            _ => "Xbox Live authorization failed",
+        };
+
+        return Err(Error::Rejected(format!("{} ({})", reason, code)));
+    }
+
+    xbox_token_from(data)
+}
+
+fn xbox_token_from(data: Value) -> Result<XboxToken, Error> {
+    Ok(XboxToken {
+        token: data["Token"].as_str().ok_or("Token str")?.to_string(),
+        user_hash: data["DisplayClaims"]["xui"][0]["uhs"]
+            .as_str()
+            .ok_or("DisplayClaims uhs str")?
+            .to_string(),
+    })
+}
+
+async fn minecraft_login(
+    client: &Client,
+    config: &MicrosoftConfig,
+    xsts: &XboxToken,
+) -> Result<String, Error> {
+    let body = json!({
+        "identityToken": format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token),
+    });
+
+    let response = client
+        .post(format!(
+            "{}/authentication/login_with_xbox",
+            config.minecraft_url
+        ))
+        .json(&body)
+        .send()
+        .await?;
+
+    let data = json_response(response).await.map_err(Error::rejected)?;
+    Ok(data["access_token"]
+        .as_str()
+        .ok_or("access_token str")?
+        .to_string())
+}
+
+/// Return the uuid and name of the player, `None` if the account
+/// doesn't have a profile
+async fn minecraft_profile(
+    client: &Client,
+    config: &MicrosoftConfig,
+    access_token: &str,
+) -> Result<Option<(String, String)>, Error> {
+    let response = client
+        .get(format!("{}/minecraft/profile", config.minecraft_url))
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+
+    let data = match json_response(response).await {
+        Err(Error::Api { status: 404, .. }) => return Ok(None),
+        result => result?,
+    };
+
+    Ok(Some((
+        data["id"].as_str().ok_or("id str")?.to_string(),
+        data["name"].as_str().ok_or("name str")?.to_string(),
+    )))
+}
+
+/// Check on the minecraft store if the account owns the game
+pub(crate) async fn owns_game(
+    client: &Client,
+    config: &MicrosoftConfig,
+    access_token: &str,
+) -> Result<bool, Error> {
+    let response = client
+        .get(format!("{}/entitlements/mcstore", config.minecraft_url))
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+
+    let data = json_response(response).await?;
+    let items = data["items"]
+        .as_array()
+        .ok_or_else(|| format!("No entitlements found: {}", data))?;
+
+    Ok(items.iter().any(|item| {
+        matches!(
+            item["name"].as_str(),
+            Some("game_minecraft") | Some("product_minecraft")
+        )
+    }))
+}
+
+/// Send a progress event, the receiver may not listen anymore
+fn notify(events: Option<&UnboundedSender<AuthEvent>>, event: AuthEvent) {
+    if let Some(events) = events {
+        let _ = events.send(event);
+    }
+}
+
+/// Exchange the microsoft token for a minecraft user
+/// going through xbox live, xsts and minecraft services
+pub(crate) async fn login_with_msa(
+    client: &Client,
+    config: &MicrosoftConfig,
+    msa: MsaToken,
+    events: Option<&UnboundedSender<AuthEvent>>,
+) -> Result<User, Error> {
+    notify(events, AuthEvent::Step(AuthStep::XboxLive));
+    let xbox = xbox_live(client, config, &msa).await?;
+
+    notify(events, AuthEvent::Step(AuthStep::Xsts));
+    let xsts = xsts(client, config, &xbox).await?;
+
+    notify(events, AuthEvent::Step(AuthStep::Minecraft));
+    let access_token = minecraft_login(client, config, &xsts).await?;
+
+    notify(events, AuthEvent::Step(AuthStep::Profile));
+    let user = User {
+        login: xsts.user_hash.clone(),
+        refresh_token: msa.refresh_token,
+        user_type: UserType::Msa,
+        provider: Provider::Microsoft(config.clone()),
+        ..Default::default()
+    };
+
+    let (uuid, name) = match minecraft_profile(client, config, &access_token).await? {
+        Some(profile) => profile,
+        None if owns_game(client, config, &access_token).await? => {
+            return Err(Error::Rejected(
+                "This account doesn't have a minecraft profile".into(),
+            ))
+        }
+        // Without the game there is no profile, only the demo can be played
+        None => {
+            return Ok(User {
+                username: DEMO_USERNAME.into(),
+                uuid: offline_uuid(DEMO_USERNAME),
+                access_token,
+                owns_game: Some(false),
+                ..user
+            })
+        }
+    };
+
+    Ok(User {
+        profiles: vec![GameProfile {
+            id: uuid.clone(),
+            name: name.clone(),
+        }],
+        username: name,
+        uuid,
+        access_token,
+        ..user
+    })
+}
+
+/// Get a new microsoft token with the refresh token of the user
+async fn refresh_msa_token(
+    client: &Client,
+    config: &MicrosoftConfig,
+    refresh_token: &str,
+) -> Result<MsaToken, Error> {
+    let response = client
+        .post(format!("{}/oauth2/v2.0/token", config.login_url))
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("client_id", config.client_id.as_str()),
+            ("scope", config.scope.as_str()),
+            ("refresh_token", refresh_token),
+        ])
+        .send()
+        .await?;
+
+    let data = json_response(response).await.map_err(Error::rejected)?;
+    let mut token = msa_token_from(&data)?;
+    if token.refresh_token.is_empty() {
+        token.refresh_token = refresh_token.to_string();
+    }
+
+    Ok(token)
+}
+
+/// Get a new minecraft access token for a microsoft user
+pub(crate) async fn refresh(
+    client: &Client,
+    config: &MicrosoftConfig,
+    refresh_token: &str,
+) -> Result<User, Error> {
+    let msa = refresh_msa_token(client, config, refresh_token).await?;
+    login_with_msa(client, config, msa, None).await
+}
+
+/// Check if the minecraft access token is still accepted
+pub(crate) async fn validate(
+    client: &Client,
+    config: &MicrosoftConfig,
+    access_token: &str,
+) -> Result<bool, Error> {
+    let response = client
+        .get(format!("{}/minecraft/profile", config.minecraft_url))
+        .bearer_auth(access_token)
+        .send()
+        .await?;
+
+    Ok(response.status().is_success())
+}
+
+/// Connect a user, the code to show is send on `events`
+pub(crate) async fn device_code_flow(
+    client: &Client,
+    config: &MicrosoftConfig,
+    events: &UnboundedSender<AuthEvent>,
+) -> Result<User, Error> {
+    let device = request_device_code(client, config).await?;
+    notify(
+        Some(events),
+        AuthEvent::DeviceCode(DeviceCode {
+            user_code: device.user_code.clone(),
+            verification_uri: device.verification_uri.clone(),
+            message: device.message.clone(),
+            expires_in: device.expires_in,
+        }),
+    );
+
+    notify(Some(events), AuthEvent::Step(AuthStep::MicrosoftToken));
+    let msa = poll_device_token(client, config, &device).await?;
+
+    login_with_msa(client, config, msa, Some(events)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        network::NetworkConfig,
+        test_server::{serve, Response},
+        user::{connect_to_microsoft, UCStatus},
+        MinecraftAuth,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn device_code_login() {
+        let polls = AtomicUsize::new(0);
+        let url = serve(move |req| {
+            assert_eq!(req.header("user-agent"), Some("launcher/1.0"));
+            match req.path.as_str() {
+            "/oauth2/v2.0/devicecode" => Response::json(
+                200,
+                json!({
+                    "device_code": "device",
+                    "user_code": "ABCD-EFGH",
+                    "verification_uri": "https://microsoft.com/link",
+                    "message": "Enter ABCD-EFGH",
+                    "expires_in": 900,
+                    "interval": 0,
+                }),
+            ),
+            "/oauth2/v2.0/token" => {
+                if polls.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Response::json(400, json!({ "error": "authorization_pending" }))
+                } else {
+                    Response::json(
+                        200,
+                        json!({ "access_token": "msa", "refresh_token": "refresh" }),
+                    )
+                }
+            }
+            "/user/authenticate" => {
+                assert_eq!(req.json()["Properties"]["RpsTicket"], "d=msa");
+                Response::json(
+                    200,
+                    json!({ "Token": "xbl", "DisplayClaims": { "xui": [{ "uhs": "hash" }] } }),
+                )
+            }
+            "/xsts/authorize" => Response::json(
+                200,
+                json!({ "Token": "xsts", "DisplayClaims": { "xui": [{ "uhs": "hash" }] } }),
+            ),
+            "/authentication/login_with_xbox" => {
+                assert_eq!(req.json()["identityToken"], "XBL3.0 x=hash;xsts");
+                Response::json(200, json!({ "access_token": "minecraft" }))
+            }
+            "/entitlements/mcstore" => Response::json(
+                200,
+                json!({ "items": [{ "name": "product_minecraft" }, { "name": "game_minecraft" }] }),
+            ),
+            "/minecraft/profile" => {
+                assert_eq!(req.method, "GET");
+                assert_eq!(req.header("authorization"), Some("Bearer minecraft"));
+                Response::json(200, json!({ "id": "uuid", "name": "Player" }))
+            }
+            _ => Response::new(404, ""),
+        }})
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into())
+            .with_network(NetworkConfig::default().with_user_agent("launcher/1.0"))
+            .unwrap();
+        let config = MicrosoftConfig::with_base_url("id".into(), &url);
+        let mut connect = connect_to_microsoft(&app, config);
+        let mut code = None;
+        let user = loop {
+            match connect.message() {
+                UCStatus::User(u) => break u,
+                UCStatus::DeviceCode(c) => code = Some(c),
+                UCStatus::Step(_) | UCStatus::Waiting => tokio::task::yield_now().await,
+                status => panic!("{:?}", status),
+            }
+        };
+
+        assert_eq!(code.unwrap().user_code, "ABCD-EFGH");
+        assert_eq!(user.login, "hash");
+        assert_eq!(user.username, "Player");
+        assert_eq!(user.uuid, "uuid");
+        assert_eq!(user.access_token, "minecraft");
+        assert_eq!(user.refresh_token, "refresh");
+        assert_eq!(user.user_type, UserType::Msa);
+        assert_eq!(user.owns_game, Some(true));
+    }
+
+    #[test]
+    fn default_poll_interval() {
+        let device: DeviceCodeResponse = serde_json::from_value(json!({
+            "device_code": "device",
+            "user_code": "ABCD-EFGH",
+            "verification_uri": "https://microsoft.com/link",
+            "expires_in": 900,
+        }))
+        .unwrap();
+        assert_eq!(device.interval, 5);
+    }
+
+    #[tokio::test]
+    async fn account_without_the_game() {
+        let url = serve(|req| match req.path.as_str() {
+            "/authentication/login_with_xbox" => {
+                Response::json(200, json!({ "access_token": "minecraft" }))
+            }
+            "/minecraft/profile" => Response::json(
+                404,
+                json!({ "path": "/minecraft/profile", "error": "NOT_FOUND" }),
+            ),
+            "/entitlements/mcstore" => Response::json(200, json!({ "items": [] })),
+            _ => Response::json(
+                200,
+                json!({ "Token": "xbl", "DisplayClaims": { "xui": [{ "uhs": "hash" }] } }),
+            ),
+        })
+        .await;
+
+        let config = MicrosoftConfig::with_base_url("id".into(), &url);
+        let msa = MsaToken {
+            access_token: "msa".into(),
+            refresh_token: "refresh".into(),
+        };
+
+        let user = login_with_msa(&Client::new(), &config, msa, None)
+            .await
+            .unwrap();
+        assert_eq!(user.login, "hash");
+        assert_eq!(user.access_token, "minecraft");
+        assert_eq!(user.owns_game, Some(false));
+        assert!(user.profiles.is_empty());
+        assert!(user.is_demo());
+    }
+
+    #[tokio::test]
+    async fn xsts_error() {
+        let url = serve(|req| match req.path.as_str() {
+            "/xsts/authorize" => Response::json(401, json!({ "XErr": 2148916233u64 })),
+            _ => Response::json(
+                200,
+                json!({ "Token": "xbl", "DisplayClaims": { "xui": [{ "uhs": "hash" }] } }),
+            ),
+        })
+        .await;
+
+        let config = MicrosoftConfig::with_base_url("id".into(), &url);
+        let msa = MsaToken {
+            access_token: "msa".into(),
+            refresh_token: "refresh".into(),
+        };
+
+        let err = login_with_msa(&Client::new(), &config, msa, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Rejected(e) if e.contains("2148916233")));
+    }
+}
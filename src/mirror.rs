@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// Url of the list of all versions
+pub const VERSION_MANIFEST_URL: &str =
+    "https://launchermeta.mojang.com/mc/game/version_manifest_v2.json";
+
+/// Base url of assets objects
+pub const RESOURCES_URL: &str = "http://resources.download.minecraft.net";
+
+/// Rewrites of urls to download from a mirror
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Mirror {
+    pub name: String,
+
+    /// Pairs of `(from, to)`. If `from` is an url prefix like
+    /// `https://libraries.minecraft.net/` it's replaced by `to`, else
+    /// it's a host like `piston-data.mojang.com` and only the scheme
+    /// and host of the url are replaced
+    pub rewrites: Vec<(String, String)>,
+}
+
+impl Mirror {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            rewrites: vec![],
+        }
+    }
+
+    /// Add a rewrite of a host or url prefix
+    pub fn rewrite(mut self, from: &str, to: &str) -> Self {
+        self.rewrites.push((from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Return the url on this mirror, `None` if the mirror doesn't have it
+    pub fn url(&self, url: &str) -> Option<String> {
+        let (scheme, rest) = url.split_once("://")?;
+        let (host, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+
+        self.rewrites.iter().find_map(|(from, to)| {
+            if from.contains("://") {
+                url.strip_prefix(from.as_str())
+                    .map(|rest| format!("{}{}", to, rest))
+            } else if host == from {
+                let to = to.trim_end_matches('/');
+                if to.contains("://") {
+                    Some(format!("{}{}", to, path))
+                } else {
+                    Some(format!("{}://{}{}", scheme, to, path))
+                }
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Mirrors used for manifests, libraries, assets and clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MirrorConfig {
+    /// Mirrors tried in this order
+    pub mirrors: Vec<Mirror>,
+
+    /// Try the original url when all mirrors failed
+    pub fallback_to_origin: bool,
+}
+
+impl Default for MirrorConfig {
+    fn default() -> Self {
+        Self {
+            mirrors: vec![],
+            fallback_to_origin: true,
+        }
+    }
+}
+
+impl MirrorConfig {
+    pub fn new(mirrors: Vec<Mirror>) -> Self {
+        Self {
+            mirrors,
+            ..Default::default()
+        }
+    }
+
+    /// All urls to try for this url, in order
+    pub fn urls(&self, url: &str) -> Vec<String> {
+        let mut urls: Vec<String> = vec![];
+        let origin = self.fallback_to_origin.then(|| url.to_string());
+        for candidate in self.mirrors.iter().filter_map(|m| m.url(url)).chain(origin) {
+            if !urls.contains(&candidate) {
+                urls.push(candidate);
+            }
+        }
+
+        urls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_urls() {
+        let config = MirrorConfig::new(vec![
+            Mirror::new("assets").rewrite("resources.download.minecraft.net", "mirror.net/assets"),
+            Mirror::new("maven")
+                .rewrite(
+                    "https://libraries.minecraft.net/",
+                    "https://mirror.net/maven/",
+                )
+                .rewrite("launchermeta.mojang.com", "https://meta.mirror.net/"),
+        ]);
+
+        assert_eq!(
+            config.urls("http://resources.download.minecraft.net/ab/abcd"),
+            vec![
+                "http://mirror.net/assets/ab/abcd",
+                "http://resources.download.minecraft.net/ab/abcd"
+            ]
+        );
+        assert_eq!(
+            config.urls("https://libraries.minecraft.net/org/lwjgl.jar")[0],
+            "https://mirror.net/maven/org/lwjgl.jar"
+        );
+        assert_eq!(
+            config.urls(VERSION_MANIFEST_URL)[0],
+            "https://meta.mirror.net/mc/game/version_manifest_v2.json"
+        );
+        assert_eq!(
+            config.urls("https://other.net/file"),
+            vec!["https://other.net/file"]
+        );
+    }
+}
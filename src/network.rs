@@ -0,0 +1,143 @@
+use crate::error::Error;
+use reqwest::{Certificate, Client, Proxy};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// User-Agent send by default
+pub const DEFAULT_USER_AGENT: &str = concat!("minecraft-auth/", env!("CARGO_PKG_VERSION"));
+
+/// Network settings used by all requests of the crate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Proxy for all requests, like `http://proxy:3128` or
+    /// `socks5://proxy:1080`. Credentials can be given in the url
+    pub proxy: Option<String>,
+
+    /// Certificates in PEM trusted on top of the default ones
+    pub root_certificates: Vec<String>,
+
+    pub connect_timeout: Option<Duration>,
+
+    /// Timeout of a whole request, big downloads can need a lot of time
+    pub timeout: Option<Duration>,
+
+    pub user_agent: String,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            root_certificates: vec![],
+            connect_timeout: Some(Duration::from_secs(30)),
+            timeout: None,
+            user_agent: DEFAULT_USER_AGENT.into(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    pub fn with_proxy(mut self, url: &str) -> Self {
+        self.proxy = Some(url.to_string());
+        self
+    }
+
+    /// Trust this certificate encoded in PEM, like an internal CA
+    pub fn with_root_certificate(mut self, pem: &str) -> Self {
+        self.root_certificates.push(pem.to_string());
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Create an http client with these settings
+    pub fn client(&self) -> Result<Client, Error> {
+        let mut builder = Client::builder().user_agent(&self.user_agent);
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy)?);
+        }
+
+        for pem in &self.root_certificates {
+            builder = builder.add_root_certificate(Certificate::from_pem(pem.as_bytes())?);
+        }
+
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Client used when no `MinecraftAuth` is given
+pub(crate) fn default_client() -> Client {
+    NetworkConfig::default().client().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        downloader::download_file,
+        test_server::{serve, Response},
+        MinecraftAuth,
+    };
+
+    #[tokio::test]
+    async fn proxy_and_user_agent() {
+        let url = serve(|req| {
+            assert_eq!(req.header("user-agent"), Some("launcher/1.0"));
+            // Requests through an http proxy use the full url
+            Response::new(200, req.path)
+        })
+        .await;
+
+        let client = NetworkConfig::default()
+            .with_proxy(&url)
+            .with_user_agent("launcher/1.0")
+            .client()
+            .unwrap();
+        let body = client
+            .get("http://example.invalid/path")
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "http://example.invalid/path");
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into())
+            .with_network(NetworkConfig::default().with_user_agent("launcher/1.0"))
+            .unwrap();
+        let path = format!("{}/file", app.path);
+        download_file(&app, format!("{}/file", url), path.clone())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "/file");
+
+        let invalid = NetworkConfig::default().with_root_certificate(
+            "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n",
+        );
+        assert!(invalid.client().is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use crate::{
+    downloader::download_file, error::Error, user::User, utils::json_response, MinecraftAuth,
+};
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+
+/// Property of a profile, `textures` contains skin and cape
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Property {
+    pub name: String,
+
+    /// Value encoded in base64
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+/// Profile given by the session server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+
+impl Profile {
+    /// Decode the `textures` property
+    pub fn textures(&self) -> Result<Option<Textures>, Error> {
+        let property = match self.properties.iter().find(|p| p.name == "textures") {
+            Some(p) => p,
+            None => return Ok(None),
+        };
+
+        let value = base64::decode(&property.value).map_err(|e| e.to_string())?;
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkinModel {
+    #[default]
+    Classic,
+    Slim,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TextureMetadata {
+    #[serde(default)]
+    pub model: SkinModel,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Texture {
+    pub url: String,
+    #[serde(default)]
+    pub metadata: TextureMetadata,
+}
+
+impl Texture {
+    /// Name of the texture, last part of the url
+    pub fn hash(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct TextureList {
+    #[serde(rename = "SKIN")]
+    pub skin: Option<Texture>,
+    #[serde(rename = "CAPE")]
+    pub cape: Option<Texture>,
+}
+
+/// Decoded `textures` property of a profile
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Textures {
+    pub timestamp: u64,
+    #[serde(rename = "profileId")]
+    pub profile_id: String,
+    #[serde(rename = "profileName")]
+    pub profile_name: String,
+    pub textures: TextureList,
+}
+
+/// Skin or cape owned by an account
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileTexture {
+    pub id: String,
+
+    /// `ACTIVE` or `INACTIVE`
+    pub state: String,
+    pub url: String,
+
+    /// Model of a skin, `CLASSIC` or `SLIM`
+    pub variant: Option<String>,
+
+    /// Name of a cape
+    pub alias: Option<String>,
+}
+
+impl ProfileTexture {
+    pub fn is_active(&self) -> bool {
+        self.state == "ACTIVE"
+    }
+}
+
+/// Profile of the account given by minecraft services
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MinecraftProfile {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub skins: Vec<ProfileTexture>,
+    #[serde(default)]
+    pub capes: Vec<ProfileTexture>,
+}
+
+async fn profile_from(
+    app: &MinecraftAuth,
+    session_server: &str,
+    uuid: &str,
+) -> Result<Profile, Error> {
+    let response = app
+        .client()
+        .get(format!(
+            "{}/session/minecraft/profile/{}?unsigned=false",
+            session_server, uuid
+        ))
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::NO_CONTENT {
+        return Err(format!("No profile found for {}", uuid).into());
+    }
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+/// Get the profile of a player with its textures from the session server
+pub async fn session_profile(app: &MinecraftAuth, uuid: &str) -> Result<Profile, Error> {
+    profile_from(app, &app.api.session_server, uuid).await
+}
+
+/// Get the skin and cape of the selected profile of the user,
+/// from the session server of its account
+pub async fn user_textures(app: &MinecraftAuth, user: &User) -> Result<Option<Textures>, Error> {
+    profile_from(app, &app.api.session_server_for(user), &user.uuid)
+        .await?
+        .textures()
+}
+
+/// Download a texture on `{app.path}/textures/{hash}.png`
+/// and return its path, already downloaded textures are reused
+pub async fn download_texture(app: &MinecraftAuth, texture: &Texture) -> Result<String, Error> {
+    let path = format!("{}/textures/{}.png", app.path, texture.hash());
+    if !Path::new(&path).exists() {
+        download_file(app, texture.url.clone(), path.clone()).await?;
+    }
+
+    Ok(path)
+}
+
+/// Get the skins and capes owned by the user
+pub async fn minecraft_profile(
+    app: &MinecraftAuth,
+    user: &User,
+) -> Result<MinecraftProfile, Error> {
+    let response = app
+        .client()
+        .get(format!("{}/minecraft/profile", app.api.services_for(user)?))
+        .bearer_auth(&user.access_token)
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+fn variant(model: SkinModel) -> &'static str {
+    match model {
+        SkinModel::Classic => "classic",
+        SkinModel::Slim => "slim",
+    }
+}
+
+/// Upload a png file as the new skin of the user
+pub async fn upload_skin(
+    app: &MinecraftAuth,
+    user: &User,
+    model: SkinModel,
+    png: Vec<u8>,
+) -> Result<MinecraftProfile, Error> {
+    let file = Part::bytes(png)
+        .file_name("skin.png")
+        .mime_str("image/png")?;
+    let form = Form::new()
+        .text("variant", variant(model))
+        .part("file", file);
+
+    let response = app
+        .client()
+        .post(format!(
+            "{}/minecraft/profile/skins",
+            app.api.services_for(user)?
+        ))
+        .bearer_auth(&user.access_token)
+        .multipart(form)
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+/// Change the skin of the user with a skin available on an url
+pub async fn change_skin_url(
+    app: &MinecraftAuth,
+    user: &User,
+    model: SkinModel,
+    url: &str,
+) -> Result<MinecraftProfile, Error> {
+    let response = app
+        .client()
+        .post(format!(
+            "{}/minecraft/profile/skins",
+            app.api.services_for(user)?
+        ))
+        .bearer_auth(&user.access_token)
+        .json(&json!({ "variant": variant(model), "url": url }))
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+/// Go back to the default skin
+pub async fn reset_skin(app: &MinecraftAuth, user: &User) -> Result<MinecraftProfile, Error> {
+    let response = app
+        .client()
+        .delete(format!(
+            "{}/minecraft/profile/skins/active",
+            app.api.services_for(user)?
+        ))
+        .bearer_auth(&user.access_token)
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+/// Show one of the capes owned by the user
+pub async fn set_cape(
+    app: &MinecraftAuth,
+    user: &User,
+    cape_id: &str,
+) -> Result<MinecraftProfile, Error> {
+    let response = app
+        .client()
+        .put(format!(
+            "{}/minecraft/profile/capes/active",
+            app.api.services_for(user)?
+        ))
+        .bearer_auth(&user.access_token)
+        .json(&json!({ "capeId": cape_id }))
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+/// Don't show any cape
+pub async fn hide_cape(app: &MinecraftAuth, user: &User) -> Result<MinecraftProfile, Error> {
+    let response = app
+        .client()
+        .delete(format!(
+            "{}/minecraft/profile/capes/active",
+            app.api.services_for(user)?
+        ))
+        .bearer_auth(&user.access_token)
+        .send()
+        .await?;
+
+    Ok(serde_json::from_value(json_response(response).await?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        provider::Provider,
+        test_server::{serve, Response},
+        ApiUrls,
+    };
+    use serde_json::Value;
+
+    fn profile_json(cape: &str) -> Value {
+        json!({
+            "id": "uuid",
+            "name": "Player",
+            "skins": [{ "id": "s", "state": "ACTIVE", "url": "http://skin", "variant": "SLIM" }],
+            "capes": [{ "id": "c", "state": cape, "url": "http://cape", "alias": "Migrator" }],
+        })
+    }
+
+    #[tokio::test]
+    async fn textures_and_skin_changes() {
+        let url = serve(|req| {
+            let host = req.header("host").unwrap().to_string();
+            match (req.method.as_str(), req.path.as_str()) {
+                (
+                    "GET",
+                    "/session/minecraft/profile/uuid?unsigned=false"
+                    | "/ygg/sessionserver/session/minecraft/profile/uuid?unsigned=false",
+                ) => {
+                    let textures = json!({
+                        "timestamp": 1,
+                        "profileId": "uuid",
+                        "profileName": "Player",
+                        "textures": {
+                            "SKIN": {
+                                "url": format!("http://{}/texture/abc", host),
+                                "metadata": { "model": "slim" },
+                            },
+                        },
+                    });
+                    Response::json(
+                        200,
+                        json!({
+                            "id": "uuid",
+                            "name": "Player",
+                            "properties": [{
+                                "name": "textures",
+                                "value": base64::encode(textures.to_string()),
+                            }],
+                        }),
+                    )
+                }
+                ("GET", "/session/minecraft/profile/other?unsigned=false") => {
+                    Response::new(204, "")
+                }
+                ("GET", "/texture/abc") => Response::new(200, "png"),
+                ("POST", "/minecraft/profile/skins") => {
+                    let content_type = req.header("content-type").unwrap();
+                    assert!(content_type.starts_with("multipart/form-data"));
+                    Response::json(200, profile_json("INACTIVE"))
+                }
+                ("PUT", "/minecraft/profile/capes/active") => {
+                    assert_eq!(req.json()["capeId"], "c");
+                    Response::json(200, profile_json("ACTIVE"))
+                }
+                _ => Response::json(401, json!({ "errorMessage": "Unauthorized" })),
+            }
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+        app.api = ApiUrls::with_base_url(&url);
+        let mut user = User::new(
+            "Player".into(),
+            "uuid".into(),
+            "client".into(),
+            "access".into(),
+        );
+
+        let textures = session_profile(&app, "uuid")
+            .await
+            .unwrap()
+            .textures()
+            .unwrap()
+            .unwrap();
+        let skin = textures.textures.skin.unwrap();
+        assert_eq!(skin.metadata.model, SkinModel::Slim);
+        assert_eq!(textures.textures.cape, None);
+
+        let path = download_texture(&app, &skin).await.unwrap();
+        assert!(path.ends_with("textures/abc.png"));
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "png");
+
+        let profile = upload_skin(&app, &user, SkinModel::Slim, vec![0; 10])
+            .await
+            .unwrap();
+        assert!(!profile.capes[0].is_active());
+
+        let profile = set_cape(&app, &user, "c").await.unwrap();
+        assert!(profile.capes[0].is_active());
+
+        assert!(reset_skin(&app, &user).await.is_err());
+        assert!(session_profile(&app, "other").await.is_err());
+
+        // Accounts of a third-party server use its session server
+        user.provider = Provider::Yggdrasil {
+            url: format!("{}/ygg", url),
+        };
+        assert!(user_textures(&app, &user).await.unwrap().is_some());
+        assert!(set_cape(&app, &user, "c").await.is_err());
+        assert!(set_cape(&app, &User::offline("Player".into()), "c")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,462 @@
+use crate::{
+    error::Error,
+    microsoft::{self, MicrosoftConfig},
+    network::default_client,
+    user::{AuthEvent, GameProfile, User, UserType},
+    utils::json_response,
+    MinecraftAuth,
+};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Default yggdrasil authentication server of mojang
+const MOJANG_AUTH_SERVER: &str = "https://authserver.mojang.com";
+
+/// Authentication backend saved with each account, used to
+/// find the right provider when the account is loaded again
+#[derive(Debug, Clone, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Provider {
+    #[default]
+    Mojang,
+    Microsoft(MicrosoftConfig),
+
+    /// Third-party yggdrasil server, `url` is the api root
+    Yggdrasil {
+        url: String,
+    },
+    Offline,
+}
+
+impl Provider {
+    /// Return the authentication provider for this backend,
+    /// its requests use the network config of the app
+    pub fn auth_provider_for(&self, app: &MinecraftAuth) -> Box<dyn AuthProvider> {
+        self.auth_provider_with(app.client().clone())
+    }
+
+    fn auth_provider_with(&self, client: Client) -> Box<dyn AuthProvider> {
+        match self {
+            Provider::Mojang => Box::new(YggdrasilProvider::mojang().with_client(client)),
+            Provider::Microsoft(config) => {
+                Box::new(MicrosoftProvider::new(config.clone()).with_client(client))
+            }
+            Provider::Yggdrasil { url } => {
+                Box::new(YggdrasilProvider::new(url).with_client(client))
+            }
+            Provider::Offline => Box::new(OfflineProvider),
+        }
+    }
+}
+
+/// Backend used to connect a user and manage its tokens
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Value saved with accounts connected with this provider
+    fn provider(&self) -> Provider;
+
+    /// Connect a user, progress can be send on `events`
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        events: &UnboundedSender<AuthEvent>,
+    ) -> Result<User, Error>;
+
+    /// Check if the access token of the user is still valid
+    async fn validate(&self, user: &User) -> Result<bool, Error>;
+
+    /// Ask a new access token for the user
+    async fn refresh(&self, user: &mut User) -> Result<(), Error>;
+
+    /// Invalidate the access token of the user
+    async fn invalidate(&self, user: &User) -> Result<(), Error>;
+
+    /// Invalidate all access tokens of an account with its credentials
+    async fn signout(&self, _login: &str, _password: &str) -> Result<(), Error> {
+        Err("This provider can't sign out accounts".into())
+    }
+
+    /// Check if the account owns the game, `None` if the
+    /// provider can't tell
+    async fn owns_game(&self, _user: &User) -> Result<Option<bool>, Error> {
+        Ok(None)
+    }
+
+    /// Select the profile used to play, from the profiles of the user
+    async fn select_profile(&self, user: &mut User, id: &str) -> Result<(), Error> {
+        user.use_profile(id)
+    }
+}
+
+/// Yggdrasil authentication, used by mojang and third-party servers
+#[derive(Debug, Clone)]
+pub struct YggdrasilProvider {
+    /// Base url of the authentication endpoints
+    pub auth_url: String,
+
+    /// Api root of a third-party server, `None` for mojang
+    pub api_root: Option<String>,
+
+    client: Client,
+}
+
+impl YggdrasilProvider {
+    pub fn mojang() -> Self {
+        Self {
+            auth_url: MOJANG_AUTH_SERVER.into(),
+            api_root: None,
+            client: default_client(),
+        }
+    }
+
+    /// Create a provider for a third-party server with its api root,
+    /// authentication endpoints are under `{api_root}/authserver`
+    pub fn new(api_root: &str) -> Self {
+        let api_root = api_root.trim_end_matches('/');
+        Self {
+            auth_url: format!("{}/authserver", api_root),
+            api_root: Some(api_root.to_string()),
+            client: default_client(),
+        }
+    }
+
+    /// Send requests with this client, like `app.client()`
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    async fn intern_refresh(
+        &self,
+        user: &mut User,
+        profile: Option<GameProfile>,
+    ) -> Result<(), Error> {
+        let mut body = json!({
+            "accessToken": user.access_token,
+            "clientToken": user.client_token,
+        });
+        if let Some(profile) = profile {
+            body["selectedProfile"] = serde_json::to_value(profile)?;
+        }
+
+        let data =
+            yggdrasil_post(&self.client, &format!("{}/refresh", self.auth_url), body).await?;
+
+        user.access_token = data["accessToken"]
+            .as_str()
+            .ok_or("accessToken str")?
+            .to_string();
+        user.client_token = data["clientToken"]
+            .as_str()
+            .ok_or("clientToken str")?
+            .to_string();
+
+        if let Ok(selected) = serde_json::from_value::<GameProfile>(data["selectedProfile"].clone())
+        {
+            user.uuid = selected.id;
+            user.username = selected.name;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthProvider for YggdrasilProvider {
+    fn provider(&self) -> Provider {
+        match &self.api_root {
+            Some(url) => Provider::Yggdrasil { url: url.clone() },
+            None => Provider::Mojang,
+        }
+    }
+
+    async fn authenticate(
+        &self,
+        login: &str,
+        password: &str,
+        _events: &UnboundedSender<AuthEvent>,
+    ) -> Result<User, Error> {
+        let data = yggdrasil_post(
+            &self.client,
+            &format!("{}/authenticate", self.auth_url),
+            json!({
+                "agent": { "name": "Minecraft", "version": 1 },
+                "username": login,
+                "password": password,
+            }),
+        )
+        .await?;
+
+        let profiles: Vec<GameProfile> =
+            serde_json::from_value(data["availableProfiles"].clone()).unwrap_or_default();
+        let selected: Option<GameProfile> =
+            serde_json::from_value(data["selectedProfile"].clone()).ok();
+        let selected = selected.unwrap_or_default();
+
+        Ok(User {
+            login: login.to_string(),
+            username: selected.name,
+            uuid: selected.id,
+            profiles,
+            client_token: data["clientToken"]
+                .as_str()
+                .ok_or("clientToken str")?
+                .to_string(),
+            access_token: data["accessToken"]
+                .as_str()
+                .ok_or("accessToken str")?
+                .to_string(),
+            user_type: UserType::Mojang,
+            provider: self.provider(),
+            ..Default::default()
+        })
+    }
+
+    async fn validate(&self, user: &User) -> Result<bool, Error> {
+        let response = self
+            .client
+            .post(format!("{}/validate", self.auth_url))
+            .json(&json!({
+                "accessToken": user.access_token,
+                "clientToken": user.client_token,
+            }))
+            .send()
+            .await?;
+
+        Ok(response.status().is_success())
+    }
+
+    async fn refresh(&self, user: &mut User) -> Result<(), Error> {
+        self.intern_refresh(user, None).await
+    }
+
+    async fn invalidate(&self, user: &User) -> Result<(), Error> {
+        yggdrasil_post(
+            &self.client,
+            &format!("{}/invalidate", self.auth_url),
+            json!({
+                "accessToken": user.access_token,
+                "clientToken": user.client_token,
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn signout(&self, login: &str, password: &str) -> Result<(), Error> {
+        yggdrasil_post(
+            &self.client,
+            &format!("{}/signout", self.auth_url),
+            json!({ "username": login, "password": password }),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Accounts without the game don't have any profile
+    async fn owns_game(&self, user: &User) -> Result<Option<bool>, Error> {
+        Ok(Some(!user.profiles.is_empty()))
+    }
+
+    /// The access token is refreshed to be bound to the new profile
+    async fn select_profile(&self, user: &mut User, id: &str) -> Result<(), Error> {
+        let profile = user
+            .profiles
+            .iter()
+            .find(|p| p.id == id)
+            .cloned()
+            .ok_or_else(|| format!("No profile with this id: {}", id))?;
+
+        self.intern_refresh(user, Some(profile)).await?;
+        user.use_profile(id)
+    }
+}
+
+/// Microsoft authentication with the device code flow
+#[derive(Debug, Clone)]
+pub struct MicrosoftProvider {
+    pub config: MicrosoftConfig,
+    client: Client,
+}
+
+impl MicrosoftProvider {
+    pub fn new(config: MicrosoftConfig) -> Self {
+        Self {
+            config,
+            client: default_client(),
+        }
+    }
+
+    /// Send requests with this client, like `app.client()`
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+#[async_trait]
+impl AuthProvider for MicrosoftProvider {
+    fn provider(&self) -> Provider {
+        Provider::Microsoft(self.config.clone())
+    }
+
+    /// Login and password are not used, the user connect
+    /// with the code send on `events`
+    async fn authenticate(
+        &self,
+        _login: &str,
+        _password: &str,
+        events: &UnboundedSender<AuthEvent>,
+    ) -> Result<User, Error> {
+        microsoft::device_code_flow(&self.client, &self.config, events).await
+    }
+
+    async fn validate(&self, user: &User) -> Result<bool, Error> {
+        microsoft::validate(&self.client, &self.config, &user.access_token).await
+    }
+
+    async fn refresh(&self, user: &mut User) -> Result<(), Error> {
+        let new = microsoft::refresh(&self.client, &self.config, &user.refresh_token).await?;
+        user.access_token = new.access_token;
+        user.refresh_token = new.refresh_token;
+
+        Ok(())
+    }
+
+    async fn owns_game(&self, user: &User) -> Result<Option<bool>, Error> {
+        microsoft::owns_game(&self.client, &self.config, &user.access_token)
+            .await
+            .map(Some)
+    }
+
+    /// Microsoft tokens can't be invalidated, they just expire
+    async fn invalidate(&self, _user: &User) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Offline users, no server is used
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct OfflineProvider;
+
+#[async_trait]
+impl AuthProvider for OfflineProvider {
+    fn provider(&self) -> Provider {
+        Provider::Offline
+    }
+
+    async fn authenticate(
+        &self,
+        login: &str,
+        _password: &str,
+        _events: &UnboundedSender<AuthEvent>,
+    ) -> Result<User, Error> {
+        Ok(User::offline(login.to_string()))
+    }
+
+    async fn validate(&self, _user: &User) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    async fn refresh(&self, _user: &mut User) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn invalidate(&self, _user: &User) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn signout(&self, _login: &str, _password: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Send a request to a yggdrasil endpoint, return an error
+/// if the server send an error message
+async fn yggdrasil_post(client: &Client, url: &str, body: Value) -> Result<Value, Error> {
+    json_response(client.post(url).json(&body).send().await?)
+        .await
+        .map_err(Error::rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{serve, Response};
+
+    #[tokio::test]
+    async fn third_party_authenticate() {
+        let url = serve(|req| match req.path.as_str() {
+            "/api/authserver/authenticate" if req.json()["password"] == "password" => {
+                assert_eq!(req.json()["username"], "player@mail.com");
+                Response::json(
+                    200,
+                    json!({
+                        "accessToken": "access",
+                        "clientToken": "client",
+                        "selectedProfile": { "id": "uuid", "name": "Player" },
+                        "availableProfiles": [
+                            { "id": "uuid", "name": "Player" },
+                            { "id": "other", "name": "Other" },
+                        ],
+                    }),
+                )
+            }
+            "/api/authserver/signout" if req.json()["password"] == "password" => {
+                Response::new(204, "")
+            }
+            "/api/authserver/refresh" => {
+                assert_eq!(req.json()["selectedProfile"]["id"], "other");
+                Response::json(
+                    200,
+                    json!({
+                        "accessToken": "other-access",
+                        "clientToken": "client",
+                        "selectedProfile": { "id": "other", "name": "Other" },
+                    }),
+                )
+            }
+            _ => Response::json(403, json!({ "errorMessage": "Invalid credentials" })),
+        })
+        .await;
+
+        let provider = YggdrasilProvider::new(&format!("{}/api/", url));
+        let (events, _) = tokio::sync::mpsc::unbounded_channel();
+        let mut user = provider
+            .authenticate("player@mail.com", "password", &events)
+            .await
+            .unwrap();
+
+        assert_eq!(user.access_token, "access");
+        assert_eq!(user.login, "player@mail.com");
+        assert_eq!(user.username, "Player");
+        assert_eq!(user.profiles.len(), 2);
+        assert_eq!(
+            user.provider,
+            Provider::Yggdrasil {
+                url: format!("{}/api", url)
+            }
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+        user.select_profile(&app, "other").await.unwrap();
+        assert_eq!(user.access_token, "other-access");
+        assert_eq!(user.username, "Other");
+        assert_eq!(user.profile().unwrap().id, "other");
+        assert!(user.select_profile(&app, "unknown").await.is_err());
+        user.signout(&app, "password").await.unwrap();
+        assert!(user.signout(&app, "wrong").await.is_err());
+
+        let err = provider
+            .authenticate("player@mail.com", "wrong", &events)
+            .await;
+        assert!(err.is_err());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{error::Error, user::User, utils::write_atomic, MinecraftAuth};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    fs,
+    path::Path,
+    sync::Mutex,
+};
+
+/// Number of pbkdf2 rounds used to derive the key from the passphrase
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// Tokens of an account, saved with a `SecretBackend`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Secrets {
+    pub access_token: String,
+    pub client_token: String,
+    pub refresh_token: String,
+
+    /// Private key of the player certificates
+    #[serde(default)]
+    pub private_key: String,
+}
+
+impl Secrets {
+    pub fn from_user(user: &User) -> Self {
+        Self {
+            access_token: user.access_token.clone(),
+            client_token: user.client_token.clone(),
+            refresh_token: user.refresh_token.clone(),
+            private_key: user
+                .certificates
+                .as_ref()
+                .map(|c| c.key_pair.private_key.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Put the secrets on the user
+    pub fn apply(self, user: &mut User) {
+        user.access_token = self.access_token;
+        user.client_token = self.client_token;
+        user.refresh_token = self.refresh_token;
+        if let Some(certificates) = &mut user.certificates {
+            certificates.key_pair.private_key = self.private_key;
+        }
+    }
+
+    /// Remove the secrets from the user
+    pub fn clear(user: &mut User) {
+        Secrets::default().apply(user);
+    }
+}
+
+/// Storage of account tokens used by the `AccountStore`.
+/// Implement it to keep tokens on an os keyring
+pub trait SecretBackend: Debug + Send + Sync {
+    /// Keep the tokens on the accounts file instead of the backend
+    fn inline(&self) -> bool {
+        false
+    }
+
+    fn get(&self, app: &MinecraftAuth, account: &str) -> Result<Option<Secrets>, Error>;
+
+    fn set(&self, app: &MinecraftAuth, account: &str, secrets: &Secrets) -> Result<(), Error>;
+
+    fn delete(&self, app: &MinecraftAuth, account: &str) -> Result<(), Error>;
+}
+
+/// Tokens are written in clear on the accounts file
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlaintextBackend;
+
+impl SecretBackend for PlaintextBackend {
+    fn inline(&self) -> bool {
+        true
+    }
+
+    fn get(&self, _app: &MinecraftAuth, _account: &str) -> Result<Option<Secrets>, Error> {
+        Ok(None)
+    }
+
+    fn set(&self, _app: &MinecraftAuth, _account: &str, _secrets: &Secrets) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn delete(&self, _app: &MinecraftAuth, _account: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    data: String,
+}
+
+/// Tokens are encrypted with ChaCha20-Poly1305 on `users_secrets.json`,
+/// the key is derived from a passphrase with pbkdf2
+pub struct EncryptedFileBackend {
+    passphrase: String,
+
+    /// Last salt used with its derived key
+    key: Mutex<Option<(Vec<u8>, [u8; 32])>>,
+}
+
+impl Debug for EncryptedFileBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedFileBackend")
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptedFileBackend {
+    pub fn new(passphrase: String) -> Self {
+        Self {
+            passphrase,
+            key: Mutex::new(None),
+        }
+    }
+
+    fn path(app: &MinecraftAuth) -> String {
+        format!("{}/users_secrets.json", app.path)
+    }
+
+    fn cipher(&self, salt: &[u8]) -> ChaCha20Poly1305 {
+        let mut cache = self.key.lock().unwrap_or_else(|e| e.into_inner());
+        let key = match cache.as_ref() {
+            Some((s, key)) if s == salt => *key,
+            _ => {
+                let mut key = [0u8; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(
+                    self.passphrase.as_bytes(),
+                    salt,
+                    PBKDF2_ROUNDS,
+                    &mut key,
+                );
+                *cache = Some((salt.to_vec(), key));
+                key
+            }
+        };
+
+        ChaCha20Poly1305::new(Key::from_slice(&key))
+    }
+
+    /// Return the salt of the file with all secrets
+    fn read(&self, app: &MinecraftAuth) -> Result<(Vec<u8>, HashMap<String, Secrets>), Error> {
+        let p = EncryptedFileBackend::path(app);
+        if !Path::new(&p).is_file() {
+            let mut salt = vec![0u8; 16];
+            rand::thread_rng().fill_bytes(&mut salt);
+            return Ok((salt, HashMap::new()));
+        }
+
+        let file: EncryptedFile = serde_json::from_str(&fs::read_to_string(&p)?)?;
+        let salt = base64::decode(file.salt).map_err(|e| e.to_string())?;
+        let nonce = base64::decode(file.nonce).map_err(|e| e.to_string())?;
+        let data = base64::decode(file.data).map_err(|e| e.to_string())?;
+
+        let plain = self
+            .cipher(&salt)
+            .decrypt(Nonce::from_slice(&nonce), data.as_ref())
+            .map_err(|_| "Can't decrypt secrets, wrong passphrase or corrupted file")?;
+
+        Ok((salt, serde_json::from_slice(&plain)?))
+    }
+
+    /// Write all secrets, a new nonce is used on each write
+    fn write(
+        &self,
+        app: &MinecraftAuth,
+        salt: &[u8],
+        secrets: &HashMap<String, Secrets>,
+    ) -> Result<(), Error> {
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let plain = serde_json::to_vec(secrets)?;
+        let data = self
+            .cipher(salt)
+            .encrypt(Nonce::from_slice(&nonce), plain.as_ref())
+            .map_err(|_| "Can't encrypt secrets")?;
+
+        let content = serde_json::to_string(&EncryptedFile {
+            version: 1,
+            salt: base64::encode(salt),
+            nonce: base64::encode(nonce),
+            data: base64::encode(data),
+        })?;
+
+        fs::create_dir_all(&app.path)?;
+        write_atomic(EncryptedFileBackend::path(app), content)?;
+
+        Ok(())
+    }
+}
+
+impl SecretBackend for EncryptedFileBackend {
+    fn get(&self, app: &MinecraftAuth, account: &str) -> Result<Option<Secrets>, Error> {
+        Ok(self.read(app)?.1.remove(account))
+    }
+
+    fn set(&self, app: &MinecraftAuth, account: &str, secrets: &Secrets) -> Result<(), Error> {
+        let (salt, mut all) = self.read(app)?;
+        if all.get(account) == Some(secrets) {
+            return Ok(());
+        }
+
+        all.insert(account.to_string(), secrets.clone());
+        self.write(app, &salt, &all)
+    }
+
+    fn delete(&self, app: &MinecraftAuth, account: &str) -> Result<(), Error> {
+        let (salt, mut all) = self.read(app)?;
+        if all.remove(account).is_some() {
+            self.write(app, &salt, &all)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::AccountStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn encrypted_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_string();
+        let app = MinecraftAuth::new("test".into(), path.clone())
+            .with_secret_backend(Arc::new(EncryptedFileBackend::new("passphrase".into())));
+
+        let mut user = User::new(
+            "Player".into(),
+            "uuid".into(),
+            "client-token".into(),
+            "access-token".into(),
+        );
+        user.refresh_token = "refresh-token".into();
+        user.save_on_file(&app).unwrap();
+
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let content = fs::read_to_string(entry.unwrap().path()).unwrap();
+            assert!(!content.contains("-token"));
+        }
+
+        assert_eq!(User::from_config(&app, "Player".into()), Some(user.clone()));
+
+        let wrong = MinecraftAuth::new("test".into(), path)
+            .with_secret_backend(Arc::new(EncryptedFileBackend::new("wrong".into())));
+        assert!(AccountStore::load(&wrong).is_err());
+
+        user.disconnect(&app).unwrap();
+        assert_eq!(
+            EncryptedFileBackend::new("passphrase".into())
+                .get(&app, "Player")
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{error::Error, profile::Profile, user::User, utils::json_response, MinecraftAuth};
+use reqwest::StatusCode;
+use serde_json::json;
+use sha1::{Digest, Sha1};
+
+/// Hash of a server used on join, this is the sha1 of all parts
+/// written as a signed number like java `BigInteger.toString(16)`
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key: &[u8]) -> String {
+    let mut hash: [u8; 20] = Sha1::new()
+        .chain_update(server_id.as_bytes())
+        .chain_update(shared_secret)
+        .chain_update(public_key)
+        .finalize()
+        .into();
+
+    let negative = hash[0] & 0x80 != 0;
+    if negative {
+        // Two's complement to get the absolute value
+        let mut carry = true;
+        for b in hash.iter_mut().rev() {
+            *b = !*b;
+            if carry {
+                let (value, overflow) = b.overflowing_add(1);
+                *b = value;
+                carry = overflow;
+            }
+        }
+    }
+
+    let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+    let hex = hex.trim_start_matches('0');
+    if negative {
+        format!("-{}", hex)
+    } else {
+        hex.to_string()
+    }
+}
+
+/// Tell the session server that the user join a server, the
+/// server check it with `has_joined` before accepting the player
+pub async fn join(app: &MinecraftAuth, user: &User, server_hash: &str) -> Result<(), Error> {
+    let response = app
+        .client()
+        .post(format!(
+            "{}/session/minecraft/join",
+            app.api.session_server_for(user)
+        ))
+        .json(&json!({
+            "accessToken": user.access_token,
+            "selectedProfile": user.uuid,
+            "serverId": server_hash,
+        }))
+        .send()
+        .await?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        json_response(response).await.map(|_| ())
+    }
+}
+
+/// Check if a player joined the server, return the profile of the
+/// player with its textures or `None` if the player didn't join
+pub async fn has_joined(
+    app: &MinecraftAuth,
+    username: &str,
+    server_hash: &str,
+    ip: Option<&str>,
+) -> Result<Option<Profile>, Error> {
+    let mut query = vec![("username", username), ("serverId", server_hash)];
+    if let Some(ip) = ip {
+        query.push(("ip", ip));
+    }
+
+    let response = app
+        .client()
+        .get(format!(
+            "{}/session/minecraft/hasJoined",
+            app.api.session_server
+        ))
+        .query(&query)
+        .send()
+        .await?;
+
+    if response.status() == StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_value(
+        json_response(response).await?,
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_server::{serve, Response},
+        ApiUrls,
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn minecraft_server_hash() {
+        assert_eq!(
+            server_hash("Notch", &[], &[]),
+            "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48"
+        );
+        assert_eq!(
+            server_hash("jeb_", &[], &[]),
+            "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1"
+        );
+        assert_eq!(
+            server_hash("simon", &[], &[]),
+            "88e16a1019277b15d58faf0541e11910eb756f6"
+        );
+    }
+
+    #[tokio::test]
+    async fn join_and_verify() {
+        let joined = Arc::new(Mutex::new(None));
+        let server = joined.clone();
+        let url = serve(move |req| {
+            if req.path == "/session/minecraft/join" {
+                let body = req.json();
+                if body["accessToken"] != "access" {
+                    return Response::json(403, json!({ "errorMessage": "Invalid token" }));
+                }
+
+                *server.lock().unwrap() = Some(body["serverId"].as_str().unwrap().to_string());
+                return Response::new(204, "");
+            }
+
+            let hash = server.lock().unwrap().clone().unwrap_or_default();
+            if req.path
+                == format!(
+                    "/session/minecraft/hasJoined?username=Player&serverId={}",
+                    hash
+                )
+            {
+                Response::json(
+                    200,
+                    json!({ "id": "uuid", "name": "Player", "properties": [] }),
+                )
+            } else {
+                Response::new(204, "")
+            }
+        })
+        .await;
+
+        let mut app = MinecraftAuth::new("test".into(), "".into());
+        app.api = ApiUrls::with_base_url(&url);
+        let mut user = User::new(
+            "Player".into(),
+            "uuid".into(),
+            "client".into(),
+            "access".into(),
+        );
+        let hash = server_hash("", b"secret", b"key");
+
+        assert_eq!(has_joined(&app, "Player", &hash, None).await.unwrap(), None);
+
+        join(&app, &user, &hash).await.unwrap();
+        assert_eq!(joined.lock().unwrap().as_deref(), Some(hash.as_str()));
+
+        let profile = has_joined(&app, "Player", &hash, None).await.unwrap();
+        assert_eq!(profile.unwrap().id, "uuid");
+
+        user.access_token = "expired".into();
+        assert!(join(&app, &user, &hash).await.is_err());
+    }
+}
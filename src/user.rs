@@ -1,14 +1,21 @@
-use crate::{error, MinecraftAuth};
-use reqwest::Client;
-use serde_json::{Map, Value};
+use crate::{
+    account::AccountStore,
+    certificates::PlayerCertificates,
+    error,
+    microsoft::{AuthStep, DeviceCode, MicrosoftConfig},
+    provider::{AuthProvider, Provider},
+    MinecraftAuth,
+};
+use futures::{stream, Future, Stream, StreamExt};
+use log::warn;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
 use std::{
-    fs::{read_to_string, File},
-    io,
-    io::{Read, Write},
-    path::Path,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
 };
 use tokio::{
-    sync::mpsc::{channel, error::TryRecvError, Receiver, Sender},
+    sync::mpsc::{channel, error::TryRecvError, unbounded_channel, Receiver, UnboundedSender},
     task::JoinHandle,
 };
 
@@ -17,7 +24,7 @@ use tokio::{
 #[derive(Debug)]
 pub enum UCStatus {
     /// return the user connection information
-    User(User),
+    User(Box<User>),
 
     /// Error of reqwest when tried to send request
     /// to minecraft api
@@ -29,13 +36,84 @@ pub enum UCStatus {
     /// Other error like channel close for receiver
     OtherError(String),
 
+    /// Code to show to the user during the microsoft connection
+    DeviceCode(DeviceCode),
+
+    /// Step currently running for the microsoft connection
+    Step(AuthStep),
+
     /// Just connection don't have error and is just not finish
     /// Is Here if you want to do something when is not ready
     Waiting,
 }
 
+impl From<AuthEvent> for UCStatus {
+    fn from(event: AuthEvent) -> Self {
+        match event {
+            AuthEvent::DeviceCode(code) => UCStatus::DeviceCode(code),
+            AuthEvent::Step(step) => UCStatus::Step(step),
+        }
+    }
+}
+
+impl From<Result<User, AuthError>> for UCStatus {
+    fn from(result: Result<User, AuthError>) -> Self {
+        match result {
+            Ok(user) => UCStatus::User(Box::new(user)),
+            Err(AuthError::Request(err)) => UCStatus::RequestError(err.to_string()),
+            Err(err) => UCStatus::ConnectionError(err.to_string()),
+        }
+    }
+}
+
+/// Progress of a connection, send while `login` is running
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthEvent {
+    /// Code to show to the user during the microsoft connection
+    DeviceCode(DeviceCode),
+
+    /// Step currently running for the microsoft connection
+    Step(AuthStep),
+}
+
+/// Error returned when a connection failed
+#[derive(Debug)]
+pub enum AuthError {
+    /// Error of reqwest when tried to send request
+    /// to the authentication server
+    Request(reqwest::Error),
+
+    /// The authentication server refused the connection
+    Connection(String),
+
+    /// Any other error, like an invalid response
+    Other(String),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Request(err) => write!(f, "Request error: {}", err),
+            AuthError::Connection(err) => write!(f, "Connection error: {}", err),
+            AuthError::Other(err) => f.write_str(err),
+        }
+    }
+}
+
+impl From<error::Error> for AuthError {
+    fn from(error: error::Error) -> Self {
+        match error {
+            error::Error::Reqwest(err) => AuthError::Request(err),
+            error::Error::Rejected(err) => AuthError::Connection(err),
+            err => AuthError::Other(err.to_string()),
+        }
+    }
+}
+
 /// This is struct to save receiver and thread
-/// where connection is currently working
+/// where connection is currently working.
+/// Prefer `login` or `login_with_progress`, this is kept
+/// for code polling `message`
 #[derive(Debug)]
 pub struct UConnect {
     receiver: Receiver<UCStatus>,
@@ -46,18 +124,23 @@ impl UConnect {
     /// Send UCStatus of the latest result of receiver
     ///
     /// # Example
-    /// ```
-    /// let u_connect = connect_to_mojang("Username".to_owned(), "Password".to_owned());
+    /// ```no_run
+    /// # use minecraft_auth::{user::{connect_to_mojang, UCStatus}, MinecraftAuth};
+    /// # async fn example(app: MinecraftAuth) {
+    /// let mut u_connect = connect_to_mojang(&app, "Username".to_owned(), "Password".to_owned());
     ///
     /// loop {
     ///     match u_connect.message() {
     ///         UCStatus::User(u) => println!("{:?}", u),
     ///         UCStatus::RequestError(err) => println!("{}", err),
     ///         UCStatus::ConnectionError(err) => println!("{}", err),
-    ///         UCStatus::OtherError(err) => {},
-    ///         UCStatus::Waiting => {},
+    ///         UCStatus::DeviceCode(code) => println!("{}", code.message),
+    ///         UCStatus::Step(step) => println!("{:?}", step),
+    ///         UCStatus::OtherError(_) => {}
+    ///         UCStatus::Waiting => {}
     ///     }
     /// }
+    /// # }
     /// ```
     pub fn message(&mut self) -> UCStatus {
         match self.receiver.try_recv() {
@@ -73,236 +156,479 @@ impl UConnect {
     }
 }
 
+/// Type of account, given to the game with `--userType`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserType {
+    #[default]
+    Mojang,
+    Msa,
+
+    /// Offline account, not connected to any server
+    Legacy,
+}
+
+impl UserType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserType::Mojang => "mojang",
+            UserType::Msa => "msa",
+            UserType::Legacy => "legacy",
+        }
+    }
+}
+
+impl FromStr for UserType {
+    type Err = error::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mojang" => Ok(UserType::Mojang),
+            "msa" => Ok(UserType::Msa),
+            "legacy" => Ok(UserType::Legacy),
+            _ => Err(format!("Unknown user type: {}", s).into()),
+        }
+    }
+}
+
+/// Game profile owned by an account
+#[derive(Debug, PartialEq, PartialOrd, Default, Clone, Serialize, Deserialize)]
+pub struct GameProfile {
+    pub id: String,
+    pub name: String,
+}
+
 /// Minecraft user information for playing game
-#[derive(Debug, PartialEq, PartialOrd, Default, Clone)]
+#[derive(Debug, PartialEq, PartialOrd, Default, Clone, Serialize, Deserialize)]
 pub struct User {
-    /// Email or Pseudo
+    /// Email or name used to connect, the xbox user hash for microsoft
+    /// accounts since their name can change
+    pub login: String,
+
+    /// Name of the player in game
     pub username: String,
 
     /// UUID of the current player
     pub uuid: String,
 
+    /// All profiles available for this account
+    #[serde(default)]
+    pub profiles: Vec<GameProfile>,
+
     /// Client token get on connection
     pub client_token: String,
 
     /// Access token for this account
     pub access_token: String,
+
+    /// Microsoft refresh token, empty for mojang account
+    #[serde(default)]
+    pub refresh_token: String,
+
+    /// Type of this account
+    #[serde(default)]
+    pub user_type: UserType,
+
+    /// Authentication backend of this account
+    #[serde(default)]
+    pub provider: Provider,
+
+    /// If the account owns the game, checked after the
+    /// connection. `None` if it's unknown
+    #[serde(default)]
+    pub owns_game: Option<bool>,
+
+    /// Keys used to sign chat messages, see `certificates`
+    #[serde(default)]
+    pub certificates: Option<PlayerCertificates>,
 }
 
 impl User {
     pub fn new(username: String, uuid: String, client_token: String, access_token: String) -> Self {
         Self {
+            login: username.clone(),
             username,
             uuid,
             client_token,
             access_token,
+            ..Default::default()
         }
     }
 
-    pub fn from_config(app: &MinecraftAuth, username: String) -> Option<Self> {
-        let p = format!("{}/users_accounts.json", app.path);
-        let path = Path::new(&p);
-        if !path.exists() || !path.is_file() {
-            return None;
+    /// Create an offline user, the uuid is the same as the one
+    /// given by a vanilla server in offline mode
+    pub fn offline(username: String) -> Self {
+        Self {
+            uuid: offline_uuid(&username),
+            login: username.clone(),
+            username,
+            user_type: UserType::Legacy,
+            provider: Provider::Offline,
+            ..Default::default()
         }
+    }
 
-        let file_content = read_to_string(path).ok()?;
-        let root: Value = serde_json::from_str(&file_content).ok()?;
-        let user = root["users"].get(&username)?;
-
-        Some(Self {
-            username,
-            uuid: user["uuid"].as_str()?.to_string(),
-            client_token: user["client_token"].as_str()?.to_string(),
-            access_token: user["access_token"].as_str()?.to_string(),
-        })
+    pub fn from_config(app: &MinecraftAuth, login: String) -> Option<Self> {
+        AccountStore::load(app).ok()?.get(&login).cloned()
     }
 
+    /// Return the active account, or the last one saved
     pub fn last_from_config(app: &MinecraftAuth) -> Option<Self> {
-        let p = format!("{}/users_accounts.json", app.path);
-        let path = Path::new(&p);
-        if !path.exists() || !path.is_file() {
-            return None;
-        }
+        let store = AccountStore::load(app).ok()?;
+        store.active().or_else(|| store.accounts().last()).cloned()
+    }
 
-        let file_content = read_to_string(path).ok()?;
-        let root = serde_json::from_str::<Value>(&file_content).ok()?;
-        let user = root["users"].as_object()?.iter().last()?;
+    /// Add or update this user on the accounts file
+    pub fn save_on_file(&self, app: &MinecraftAuth) -> Result<(), error::Error> {
+        let mut store = AccountStore::load(app)?;
+        store.upsert(self.clone())?;
+        store.save(app)
+    }
 
-        Some(Self {
-            username: user.0.clone(),
-            uuid: user.1["uuid"].as_str()?.to_string(),
-            client_token: user.1["client_token"].as_str()?.to_string(),
-            access_token: user.1["access_token"].as_str()?.to_string(),
-        })
+    /// Remove this user from the accounts file
+    pub fn disconnect(&self, app: &MinecraftAuth) -> Result<(), error::Error> {
+        let mut store = AccountStore::load(app)?;
+        store.remove(&self.login)?;
+        store.save(app)
     }
 
-    pub fn save_on_file(&self, app: &MinecraftAuth) -> Result<(), error::Error> {
-        let mut file = User::open_user_file(app)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
+    /// Return the selected profile
+    pub fn profile(&self) -> Option<&GameProfile> {
+        self.profiles.iter().find(|p| p.id == self.uuid)
+    }
 
-        if content.is_empty() {
-            content += "{}";
-        }
+    /// Use a profile of `profiles` to play, `uuid` and
+    /// `username` are changed to the profile information
+    pub(crate) fn use_profile(&mut self, id: &str) -> Result<(), error::Error> {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|p| p.id == id)
+            .ok_or_else(|| format!("No profile with this id: {}", id))?;
+
+        self.uuid = profile.id.clone();
+        self.username = profile.name.clone();
+        Ok(())
+    }
 
-        let root: Value = serde_json::from_str(&content)?;
-        let el = match root {
-            Value::Object(mut r) => {
-                if r.contains_key("users") {
-                    if let Some(users) = r["users"].as_object_mut() {
-                        if !users.contains_key(&self.username) {
-                            return Err(
-                                format!("No user with this username: {}", self.username).into()
-                            );
-                        }
-
-                        if let Some(user) = users[&self.username].as_object_mut() {
-                            user["uuid"] = Value::String(self.uuid.clone());
-                            user["access_token"] = Value::String(self.access_token.clone());
-                            user["client_token"] = Value::String(self.client_token.clone());
-                        } else {
-                            let mut user = Map::new();
-                            user.insert(
-                                self.username.clone(),
-                                Value::Object(self.convert_to_map()),
-                            );
-
-                            users.insert(self.username.clone(), Value::Object(user));
-                        }
-                    }
-                } else {
-                    let mut user = Map::new();
-                    user.insert(self.username.clone(), Value::Object(self.convert_to_map()));
-                    r.insert("users".into(), Value::Object(user));
-                }
+    /// Select the profile used to play with the provider of this account
+    pub async fn select_profile(
+        &mut self,
+        app: &MinecraftAuth,
+        id: &str,
+    ) -> Result<(), error::Error> {
+        self.provider
+            .clone()
+            .auth_provider_for(app)
+            .select_profile(self, id)
+            .await
+    }
 
-                Value::Object(r)
-            }
-            v => v,
-        };
+    /// Check with the provider of this account if the access token is still valid
+    pub async fn validate(&self, app: &MinecraftAuth) -> Result<bool, error::Error> {
+        self.provider.auth_provider_for(app).validate(self).await
+    }
 
-        let new_content = serde_json::to_string(&el)?;
-        file.write_all(new_content.as_bytes())?;
+    /// Ask a new access token to the provider of this account
+    pub async fn refresh(&mut self, app: &MinecraftAuth) -> Result<(), error::Error> {
+        self.provider
+            .clone()
+            .auth_provider_for(app)
+            .refresh(self)
+            .await
+    }
 
-        Ok(())
+    /// Invalidate the access token with the provider of this account
+    pub async fn invalidate(&self, app: &MinecraftAuth) -> Result<(), error::Error> {
+        self.provider.auth_provider_for(app).invalidate(self).await
     }
 
-    fn convert_to_map(&self) -> Map<String, Value> {
-        let mut user_info = Map::new();
-        user_info.insert("uuid".to_string(), Value::String(self.uuid.clone()));
-        user_info.insert(
-            "access_token".to_string(),
-            Value::String(self.access_token.clone()),
-        );
-        user_info.insert(
-            "client_token".to_string(),
-            Value::String(self.client_token.clone()),
-        );
+    /// Invalidate all access tokens of this account, the
+    /// password is needed by the provider
+    pub async fn signout(&self, app: &MinecraftAuth, password: &str) -> Result<(), error::Error> {
+        self.provider
+            .auth_provider_for(app)
+            .signout(&self.login, password)
+            .await
+    }
 
-        user_info
+    /// Ask the provider of this account if the game is owned, the
+    /// result is kept on `owns_game`
+    pub async fn check_ownership(
+        &mut self,
+        app: &MinecraftAuth,
+    ) -> Result<Option<bool>, error::Error> {
+        self.owns_game = self.provider.auth_provider_for(app).owns_game(self).await?;
+        Ok(self.owns_game)
     }
 
-    pub fn disconnect(&self, app: &MinecraftAuth) -> Result<(), error::Error> {
-        let mut file = User::open_user_file(app)?;
-        let mut content = String::new();
-        file.read_to_string(&mut content)?;
+    /// The account is known to not own the game,
+    /// it can only play the demo
+    pub fn is_demo(&self) -> bool {
+        self.owns_game == Some(false)
+    }
 
-        if content.is_empty() || content == "\n" {
+    /// Make sure this user can be used to start the game, the access
+    /// token is refreshed if needed and saved on the users file
+    pub async fn ensure_valid(&mut self, app: &MinecraftAuth) -> Result<(), error::Error> {
+        if self.validate(app).await? {
             return Ok(());
         }
 
-        let root: Value = serde_json::from_str(&content)?;
-        let el = match root {
-            Value::Object(mut r) => {
-                let arr = r["users"].as_object_mut().ok_or("No found 'user' key")?;
-                arr.remove(&self.username).ok_or("Can't remove user")?;
-                Value::Object(r)
+        self.refresh(app).await?;
+        self.save_on_file(app)
+    }
+}
+
+/// This is the intern connection function for all providers, the game
+/// ownership is checked after the connection and stays unknown if the
+/// check fails. `events` is dropped at the end to close the progress stream
+async fn intern_login(
+    provider: &dyn AuthProvider,
+    login: &str,
+    password: &str,
+    events: UnboundedSender<AuthEvent>,
+) -> Result<User, AuthError> {
+    let mut user = provider.authenticate(login, password, &events).await?;
+    if user.owns_game.is_none() {
+        match provider.owns_game(&user).await {
+            Ok(owns_game) => user.owns_game = owns_game,
+            Err(err) => warn!("Can't check if {} owns the game: {}", user.username, err),
+        }
+    }
+
+    Ok(user)
+}
+
+/// Connect a user with any authentication provider, build it with
+/// `Provider::auth_provider_for` to use the network config of the app
+///
+/// # Example
+/// ```no_run
+/// # use minecraft_auth::{provider::Provider, user::login, MinecraftAuth};
+/// # async fn example(app: MinecraftAuth) {
+/// let provider = Provider::Mojang.auth_provider_for(&app);
+/// match login(provider.as_ref(), "Player", "password").await {
+///     Ok(user) => println!("{}", user.username),
+///     Err(err) => println!("{}", err),
+/// }
+/// # }
+/// ```
+pub async fn login(
+    provider: &dyn AuthProvider,
+    login: &str,
+    password: &str,
+) -> Result<User, AuthError> {
+    let (events, _) = unbounded_channel();
+    intern_login(provider, login, password, events).await
+}
+
+/// Same as `login`, with a stream of the progress of the connection.
+/// The stream ends when the connection is finished, events are kept
+/// until read so the stream can be polled after the future
+///
+/// # Example
+/// ```no_run
+/// # use futures::StreamExt;
+/// # use minecraft_auth::{microsoft::MicrosoftConfig, provider::Provider, user::*, MinecraftAuth};
+/// # async fn example(app: MinecraftAuth) {
+/// let config = MicrosoftConfig::new("client-id".into());
+/// let provider = Provider::Microsoft(config).auth_provider_for(&app);
+/// let (user, mut progress) = login_with_progress(provider.as_ref(), "", "");
+///
+/// let show = async {
+///     while let Some(event) = progress.next().await {
+///         if let AuthEvent::DeviceCode(code) = event {
+///             println!("{}", code.message);
+///         }
+///     }
+/// };
+/// let (user, _) = futures::join!(user, show);
+/// # }
+/// ```
+pub fn login_with_progress<'a>(
+    provider: &'a dyn AuthProvider,
+    login: &'a str,
+    password: &'a str,
+) -> (
+    impl Future<Output = Result<User, AuthError>> + 'a,
+    impl Stream<Item = AuthEvent> + Unpin,
+) {
+    let (events, receiver) = unbounded_channel();
+    let progress = Box::pin(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|event| (event, receiver))
+    }));
+
+    (intern_login(provider, login, password, events), progress)
+}
+
+/// Name based uuid (version 3) of `OfflinePlayer:<username>`, without dashes
+pub fn offline_uuid(username: &str) -> String {
+    let mut hash = Md5::digest(format!("OfflinePlayer:{}", username).as_bytes());
+    hash[6] = (hash[6] & 0x0f) | 0x30;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Try to connect with any authentication provider
+pub fn connect<P>(provider: P, login: String, password: String) -> UConnect
+where
+    P: AuthProvider + 'static,
+{
+    intern_connect(Box::new(provider), login, password)
+}
+
+fn intern_connect(provider: Box<dyn AuthProvider>, login: String, password: String) -> UConnect {
+    let (sender, receiver) = channel(10);
+    let thread = tokio::spawn(async move {
+        let (user, mut progress) = login_with_progress(provider.as_ref(), &login, &password);
+        let forward = async {
+            while let Some(event) = progress.next().await {
+                let _ = sender.send(event.into()).await;
             }
-            v => v,
         };
 
-        let new_content = serde_json::to_string(&el).unwrap();
-        file.write_all(new_content.as_bytes()).unwrap();
+        let (user, _) = tokio::join!(user, forward);
 
-        Ok(())
-    }
+        // Nothing to do if the UConnect was dropped
+        let _ = sender.send(user.into()).await;
+    });
 
-    fn open_user_file(app: &MinecraftAuth) -> io::Result<File> {
-        File::options()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(format!("{}/users_accounts.json", app.path))
+    UConnect {
+        receiver,
+        _thread: thread,
     }
 }
 
-/// This is the intern connection function for mojang minecraft api
-async fn intern_connect(
-    username: String,
-    password: String,
-    sender: Sender<UCStatus>,
-) -> Result<(), error::Error> {
-    let client = Client::new();
-    let body = format!("{{\"agent\": {{\"name\": \"Minecraft\",\"version\":1}},\"username\":\"{}\",\"password\":\"{}\"}}", username, password);
-    let res = client
-        .post("https://authserver.mojang.com/authenticate")
-        .body(body)
-        .send()
-        .await;
+/// Try to connect to mojang api with Username and Password
+// Remove this to just use microsoft connect method
+pub fn connect_to_mojang(app: &MinecraftAuth, username: String, password: String) -> UConnect {
+    intern_connect(Provider::Mojang.auth_provider_for(app), username, password)
+}
 
-    let data: Value = match res {
-        Ok(val) => {
-            let data = val.text().await?;
-            serde_json::from_str(&data)?
-        }
-        Err(err) => {
-            sender.send(UCStatus::RequestError(err.to_string())).await?;
-            return Ok(());
-        }
+/// Try to connect with a microsoft account using the device code flow.
+/// The code to show to the user is send with `UCStatus::DeviceCode`
+pub fn connect_to_microsoft(app: &MinecraftAuth, config: MicrosoftConfig) -> UConnect {
+    let provider = Provider::Microsoft(config).auth_provider_for(app);
+    intern_connect(provider, String::new(), String::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        provider::YggdrasilProvider,
+        test_server::{serve, Response},
     };
+    use serde_json::json;
 
-    if let Some(error) = data["errorMessage"].as_str() {
-        sender
-            .send(UCStatus::ConnectionError(error.to_string()))
-            .await?;
-    } else {
-        let client_token = data["clientToken"]
-            .as_str()
-            .ok_or("clientToken str")?
-            .to_string();
-        let access_token = data["accessToken"]
-            .as_str()
-            .ok_or("accessToken str")?
-            .to_string();
-        let uuid = data["selectedProfile"]["id"]
-            .as_str()
-            .ok_or("selectedProfile id str")?
-            .to_string();
-
-        sender
-            .send(UCStatus::User(User::new(
-                username,
-                uuid,
-                client_token,
-                access_token,
-            )))
-            .await?;
+    #[test]
+    fn offline_user() {
+        let user = User::offline("Notch".into());
+        assert_eq!(user.uuid, "b50ad385829d3141a2167e7d7539ba7f");
+        assert_eq!(user.user_type, UserType::Legacy);
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn login_errors_are_returned() {
+        let url =
+            serve(|_| Response::json(403, json!({ "errorMessage": "Invalid credentials" }))).await;
+        let provider = YggdrasilProvider::new(&url);
 
-/// Try to connect to mojang api with Username and Password
-// Remove this to just use microsoft connect method
-pub fn connect_to_mojang(username: String, password: String) -> UConnect {
-    let (sender, receiver) = channel(1);
-    let thread =
-        tokio::spawn(async move { intern_connect(username, password, sender).await.unwrap() });
+        let err = login(&provider, "Player", "wrong").await.unwrap_err();
+        assert!(matches!(err, AuthError::Connection(e) if e == "Invalid credentials"));
 
-    UConnect {
-        receiver,
-        _thread: thread,
+        let mut connect = connect(provider, "Player".into(), "wrong".into());
+        let status = loop {
+            match connect.message() {
+                UCStatus::Waiting => tokio::task::yield_now().await,
+                status => break status,
+            }
+        };
+        assert!(matches!(status, UCStatus::ConnectionError(_)));
+    }
+
+    #[tokio::test]
+    async fn malformed_login_response_is_not_a_refusal() {
+        let url = serve(|_| Response::json(200, json!({ "clientToken": "client" }))).await;
+        let provider = YggdrasilProvider::new(&url);
+
+        let err = login(&provider, "Player", "password").await.unwrap_err();
+        assert!(matches!(err, AuthError::Other(e) if e == "accessToken str"));
+    }
+
+    #[tokio::test]
+    async fn failed_ownership_check_keeps_the_login() {
+        struct Unavailable;
+
+        #[async_trait::async_trait]
+        impl AuthProvider for Unavailable {
+            fn provider(&self) -> Provider {
+                Provider::Offline
+            }
+
+            async fn authenticate(
+                &self,
+                login: &str,
+                _password: &str,
+                _events: &UnboundedSender<AuthEvent>,
+            ) -> Result<User, error::Error> {
+                Ok(User::offline(login.into()))
+            }
+
+            async fn validate(&self, _user: &User) -> Result<bool, error::Error> {
+                Ok(true)
+            }
+
+            async fn refresh(&self, _user: &mut User) -> Result<(), error::Error> {
+                Ok(())
+            }
+
+            async fn invalidate(&self, _user: &User) -> Result<(), error::Error> {
+                Ok(())
+            }
+
+            async fn owns_game(&self, _user: &User) -> Result<Option<bool>, error::Error> {
+                Err("Request failed (503): Service Unavailable".into())
+            }
+        }
+
+        let user = login(&Unavailable, "Player", "").await.unwrap();
+        assert_eq!(user.username, "Player");
+        assert_eq!(user.owns_game, None);
+    }
+
+    #[tokio::test]
+    async fn ensure_valid_refresh_expired_token() {
+        let url = serve(|req| match req.path.as_str() {
+            "/authserver/validate" => {
+                Response::json(403, json!({ "errorMessage": "Invalid token" }))
+            }
+            "/authserver/refresh" => {
+                assert_eq!(req.json()["accessToken"], "old");
+                Response::json(
+                    200,
+                    json!({ "accessToken": "new", "clientToken": "client" }),
+                )
+            }
+            _ => Response::new(404, ""),
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+
+        let mut user = User::new(
+            "Player".into(),
+            "uuid".into(),
+            "client".into(),
+            "old".into(),
+        );
+        user.provider = Provider::Yggdrasil { url };
+        user.ensure_valid(&app).await.unwrap();
+
+        assert_eq!(user.access_token, "new");
+        assert_eq!(User::from_config(&app, "Player".into()), Some(user));
     }
 }
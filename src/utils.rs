@@ -6,3 +6,78 @@ macro_rules! scan {
 }
 
 pub(crate) use scan;
+
+use crate::error::Error;
+use reqwest::Response;
+use serde_json::Value;
+use std::{fs, io, path::Path};
+
+/// Return the json body, `Null` if it's empty. An `Error::Api` with
+/// the body is returned if the status is not a success
+pub(crate) async fn json_response(response: Response) -> Result<Value, Error> {
+    let status = response.status();
+    let text = response.text().await?;
+    let body: Value = if text.trim().is_empty() {
+        Value::Null
+    } else if status.is_success() {
+        serde_json::from_str(&text)?
+    } else {
+        serde_json::from_str(&text).unwrap_or_default()
+    };
+
+    if !status.is_success() {
+        let message = ["errorMessage", "error_description", "error"]
+            .iter()
+            .find_map(|field| body[field].as_str())
+            .unwrap_or(&text)
+            .to_string();
+
+        return Err(Error::Api {
+            status: status.as_u16(),
+            message,
+            body,
+        });
+    }
+
+    Ok(body)
+}
+
+/// Write on a temporary file then rename it, to never leave a partial file
+pub(crate) fn write_atomic(path: impl AsRef<Path>, content: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)
+}
+
+/// Seconds since unix epoch
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Parse a UTC date like `2022-07-28T01:16:55.212806Z`,
+/// return seconds since unix epoch
+pub(crate) fn parse_rfc3339(date: &str) -> Option<u64> {
+    let date = date.trim_end_matches('Z').trim_end_matches("+00:00");
+    let (day, time) = date.split_once('T')?;
+    let time = time.split('.').next()?;
+
+    let (y, m, d) = scan!(day, '-', i64, i64, i64);
+    let (h, min, s) = scan!(time, ':', i64, i64, i64);
+    let (y, m, d) = (y?, m?, d?);
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    u64::try_from(days * 86400 + h? * 3600 + min? * 60 + s?).ok()
+}
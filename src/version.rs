@@ -4,16 +4,60 @@ use crate::{
         download::{Artifact, Classifier},
         library::Library,
         package::Package,
-        version::{ManifestVersion, Version},
+        version::{ManifestVersion, Version, VersionType},
     },
-    downloader::{download_file, FileInfo},
+    downloader::{file_sha1, CancellationToken, Downloader, FileDownload, FileInfo},
     error::{self, Error},
+    mirror::{RESOURCES_URL, VERSION_MANIFEST_URL},
     native::os_native_name,
+    utils::{now, write_atomic},
     MinecraftAuth,
 };
-use log::info;
-use serde::Deserialize;
-use std::{fs::File, io::BufReader, path::Path};
+use log::{info, warn};
+use reqwest::{
+    header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+    time::Duration,
+};
+
+/// Time the list of versions is used before asking mojang if it changed
+pub const DEFAULT_MANIFEST_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// When the list of versions is asked again to the server
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ManifestRefresh {
+    /// Use the saved list, it's only downloaded if missing
+    Never,
+
+    /// Ask the server if the saved list is older than this
+    After(Duration),
+
+    /// Ask the server each time
+    Always,
+}
+
+impl Default for ManifestRefresh {
+    fn default() -> Self {
+        Self::After(DEFAULT_MANIFEST_TTL)
+    }
+}
+
+/// Headers of the last answer of the server, send back
+/// to only download the list when it changed
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ManifestCache {
+    etag: Option<String>,
+    last_modified: Option<String>,
+
+    /// Seconds since unix epoch
+    checked: u64,
+}
 
 fn intern_manifest<T>(p: &str) -> Result<T, Error>
 where
@@ -38,43 +82,240 @@ where
     intern_manifest::<T>(&format!("{}/assets/indexes/{}.json", app.path, version))
 }
 
-async fn download_manifest(path: &str, url: &str, id: &str) -> Result<(), error::Error> {
-    download_file(url.to_string(), format!("{}/{}.json", path, id)).await
+/// Download the list of versions on `path` if the server has a new one
+async fn fetch_manifest(
+    app: &MinecraftAuth,
+    path: &str,
+    cache: &mut ManifestCache,
+    conditional: bool,
+) -> Result<(), Error> {
+    let mut result = Err("No url to download the list of versions".into());
+    for url in app.mirrors.urls(VERSION_MANIFEST_URL) {
+        let mut request = app.client().get(&url);
+        if conditional {
+            if let Some(etag) = &cache.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cache.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        result = async {
+            let response = request.send().await?;
+            if conditional && response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(());
+            }
+
+            if !response.status().is_success() {
+                return Err(Error::Http {
+                    url: url.clone(),
+                    status: response.status().as_u16(),
+                    retry_after: None,
+                });
+            }
+
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|h| h.to_str().ok())
+                    .map(str::to_string)
+            };
+            cache.etag = header(ETAG);
+            cache.last_modified = header(LAST_MODIFIED);
+
+            let content = response.bytes().await?;
+            serde_json::from_slice::<ManifestVersion>(&content)?;
+
+            Ok(write_atomic(path, &content)?)
+        }
+        .await;
+
+        match &result {
+            Ok(_) => break,
+            Err(err) => warn!("Can't download the list of versions from {}: {}", url, err),
+        }
+    }
+
+    result
+}
+
+async fn load_manifest(
+    app: &MinecraftAuth,
+    refresh: ManifestRefresh,
+    cancel: &CancellationToken,
+) -> Result<ManifestVersion, Error> {
+    let dir = format!("{}/versions", app.path);
+    let path = format!("{}/manifest_version.json", dir);
+    let cache_path = format!("{}/manifest_version.cache.json", dir);
+
+    let saved: Option<ManifestVersion> = intern_manifest(&path).ok();
+    let mut cache: ManifestCache = fs::read_to_string(&cache_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+
+    let up_to_date = match refresh {
+        ManifestRefresh::Never => true,
+        ManifestRefresh::After(ttl) => now().saturating_sub(cache.checked) < ttl.as_secs(),
+        ManifestRefresh::Always => false,
+    };
+    if let (true, Some(saved)) = (up_to_date, &saved) {
+        return Ok(saved.clone());
+    }
+
+    fs::create_dir_all(&dir)?;
+    let fetched = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => Err(Error::Cancelled),
+        result = fetch_manifest(app, &path, &mut cache, saved.is_some()) => result,
+    };
+
+    match (fetched, saved) {
+        (Ok(_), _) => {
+            cache.checked = now();
+            if let Err(err) = serde_json::to_string(&cache)
+                .map_err(Error::from)
+                .and_then(|content| Ok(fs::write(&cache_path, content)?))
+            {
+                warn!("Can't save {}: {}", cache_path, err);
+            }
+
+            intern_manifest(&path)
+        }
+        (Err(Error::Cancelled), _) => Err(Error::Cancelled),
+        (Err(err), Some(saved)) => {
+            warn!("Use the saved list of versions: {}", err);
+            Ok(saved)
+        }
+        (Err(err), None) => Err(err),
+    }
+}
+
+/// Return the list of all versions, it's asked again to the server
+/// following `refresh` and only downloaded if it changed
+pub async fn versions_manifest(
+    app: &MinecraftAuth,
+    refresh: ManifestRefresh,
+) -> Result<ManifestVersion, Error> {
+    load_manifest(app, refresh, &CancellationToken::new()).await
+}
+
+/// Version of the list of versions and if it's already installed
+#[derive(Debug, Clone)]
+pub struct VersionEntry {
+    pub version: Version,
+    pub installed: bool,
+}
+
+/// Filter used by `list_versions`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VersionFilter {
+    /// Keep only these types, all versions if empty
+    pub types: Vec<VersionType>,
+    pub installed_only: bool,
+}
+
+impl VersionFilter {
+    pub fn types(types: &[VersionType]) -> Self {
+        Self {
+            types: types.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Return the versions of `manifest` matching this
+    /// filter, newest first like in the manifest
+    pub fn apply(&self, app: &MinecraftAuth, manifest: &ManifestVersion) -> Vec<VersionEntry> {
+        manifest
+            .versions
+            .iter()
+            .filter(|v| self.types.is_empty() || self.types.contains(&v.version_type()))
+            .map(|v| VersionEntry {
+                version: v.clone(),
+                installed: is_installed(app, &v.id),
+            })
+            .filter(|entry| !self.installed_only || entry.installed)
+            .collect()
+    }
+}
+
+/// The manifest and the client of the version are downloaded,
+/// use `file_to_download_for_version_verified` to check all files
+pub fn is_installed(app: &MinecraftAuth, id: &str) -> bool {
+    Path::new(&format!("{}/versions/{}.json", app.path, id)).exists()
+        && Path::new(&format!("{}/clients/{}/client.jar", app.path, id)).exists()
+}
+
+/// List versions matching `filter`, the list is refreshed
+/// following `app.manifest_refresh`
+///
+/// # Examples
+/// ```no_run
+/// # use minecraft_auth::{data::version::VersionType, version::*, MinecraftAuth};
+/// # async fn example(app: MinecraftAuth) {
+/// let filter = VersionFilter::types(&[VersionType::Release]);
+/// for entry in list_versions(&app, &filter).await.unwrap() {
+///     println!("{} {}", entry.version.id, entry.installed);
+/// }
+/// # }
+/// ```
+pub async fn list_versions(
+    app: &MinecraftAuth,
+    filter: &VersionFilter,
+) -> Result<Vec<VersionEntry>, Error> {
+    let manifest = versions_manifest(app, app.manifest_refresh).await?;
+    Ok(filter.apply(app, &manifest))
+}
+
+/// Download the manifest of a version if missing, or if mojang
+/// changed it since it was downloaded
+async fn update_version_manifest(
+    app: &MinecraftAuth,
+    downloader: &Downloader,
+    version: &Version,
+) -> Result<(), Error> {
+    let path = format!("{}/versions/{}.json", app.path, version.id);
+    let outdated = match &version.sha1 {
+        Some(sha1) => file_sha1(&path).map_or(true, |found| !found.eq_ignore_ascii_case(sha1)),
+        None => manifest::<Package>(app, &version.id).is_err(),
+    };
+
+    if outdated {
+        let mut file = FileInfo::new(version.url.clone(), path, 0);
+        file.sha1 = version.sha1.clone();
+        downloader.download(&file).await?;
+    }
+
+    Ok(())
 }
 
 fn add_download_with_lib_info(
     infos: &Artifact,
     lib_path: &str,
     files: &mut Vec<FileInfo>,
-) -> Option<()> {
-    let url = infos.url.clone();
+    verify_hash: bool,
+) {
     let path = format!("{}{}", lib_path, infos.path.clone().unwrap_or_default());
+    let file = FileInfo::new(infos.url.clone(), path, infos.size).with_sha1(infos.sha1.clone());
 
-    let p = Path::new(&path);
-    if !p.exists() {
-        files.push(FileInfo::new(url.to_string(), path, infos.size));
-        return Some(());
+    if file.needs_download(verify_hash) {
+        files.push(file);
     }
-
-    let file = File::open(&path).ok()?;
-    if file.metadata().ok()?.len() != infos.size {
-        files.push(FileInfo::new(url.to_string(), path, infos.size));
-    }
-
-    Some(())
 }
 
-// Find a way to return a downloader to user with all download file
 async fn download_libraries(
     app: &MinecraftAuth,
-    libs: &Vec<Library>,
+    libs: &[Library],
     files: &mut Vec<FileInfo>,
+    verify_hash: bool,
 ) -> Option<()> {
     let lib_path = format!("{}/libraries/", app.path);
 
     libs.iter().for_each(|lib| {
         if let Some(artifact) = &lib.downloads.artifact {
-            add_download_with_lib_info(artifact, &lib_path, files);
+            add_download_with_lib_info(artifact, &lib_path, files, verify_hash);
         }
 
         if let Some(classifiers) = &lib.downloads.classifiers {
@@ -90,7 +331,7 @@ async fn download_libraries(
                 }
             };
 
-            add_download_with_lib_info(classifier, &lib_path, files);
+            add_download_with_lib_info(classifier, &lib_path, files, verify_hash);
         }
     });
 
@@ -102,34 +343,35 @@ async fn download_client(
     client: &Artifact,
     version: &str,
     files: &mut Vec<FileInfo>,
-) -> Option<()> {
+    verify_hash: bool,
+) {
     let path = format!("{}/clients/{}/client.jar", app.path, version);
+    let file = FileInfo::new(client.url.clone(), path, client.size).with_sha1(client.sha1.clone());
 
-    let p = Path::new(&path);
-    if !p.exists() {
-        files.push(FileInfo::new(client.url.clone(), path, client.size));
-        return Some(());
+    if file.needs_download(verify_hash) {
+        files.push(file);
     }
-
-    let file = File::open(&path).ok()?;
-    if file.metadata().ok()?.len() != client.size {
-        files.push(FileInfo::new(client.url.clone(), path, client.size));
-    }
-
-    Some(())
 }
 
 async fn download_assets(
     app: &MinecraftAuth,
+    downloader: &Downloader,
     assets: &AssetIndex,
     files: &mut Vec<FileInfo>,
+    verify_hash: bool,
 ) -> Result<(), Error> {
     let id = &assets.id;
-    let url = &assets.url;
     let path = format!("{}/assets", app.path);
-    let indexes_path = format!("{}/indexes/", path);
 
-    download_manifest(&indexes_path, url, id).await?;
+    let index = FileInfo::new(
+        assets.url.clone(),
+        format!("{}/indexes/{}.json", path, id),
+        assets.size,
+    )
+    .with_sha1(assets.sha1.clone());
+    if index.needs_download(true) {
+        downloader.download(&index).await?;
+    }
 
     let assets: Assets = version_manifest(app, id)?;
     files.append(
@@ -140,18 +382,10 @@ async fn download_assets(
                 let hash = o.1.hash.clone();
                 let b = &hash[..2];
                 let p = format!("{}/objects/{}/{}", path, b, hash);
-                let url = format!("http://resources.download.minecraft.net/{}/{}", b, hash);
-                let size = o.1.size;
-
-                let path = Path::new(&p);
-                if path.exists() {
-                    let file = File::open(&p).ok()?;
-                    if file.metadata().ok()?.len() == size {
-                        return None;
-                    }
-                }
+                let url = format!("{}/{}/{}", RESOURCES_URL, b, hash);
 
-                Some(FileInfo::new(url, p, size))
+                let file = FileInfo::new(url, p, o.1.size).with_sha1(hash);
+                file.needs_download(verify_hash).then_some(file)
             })
             .collect(),
     );
@@ -161,64 +395,115 @@ async fn download_assets(
 
 async fn find_and_install_minecraft_version(
     app: &MinecraftAuth,
+    downloader: &Downloader,
     version: &str,
-    versions: &Vec<Version>,
+    versions: &ManifestVersion,
     files: &mut Vec<FileInfo>,
+    verify_hash: bool,
 ) -> Result<(), error::Error> {
     let v = versions
-        .iter()
-        .find(|v| version == v.id)
-        .ok_or("No version found")?;
+        .find(version)
+        .ok_or_else(|| format!("No version found: {}", version))?;
 
-    let package: Package = match manifest(app, &v.id) {
-        Ok(m) => m,
-        _ => {
-            let path = format!("{}/versions/", app.path);
-            download_manifest(&path, &v.url, &v.id).await?;
+    update_version_manifest(app, downloader, v).await?;
+    let package: Package = manifest(app, &v.id)?;
 
-            manifest(app, &v.id)?
-        }
-    };
-
-    download_libraries(app, &package.libraries, files).await;
-    download_client(app, &package.downloads.client, version, files).await;
-    download_assets(app, &package.asset_index, files).await?;
+    download_libraries(app, &package.libraries, files, verify_hash).await;
+    download_client(app, &package.downloads.client, &v.id, files, verify_hash).await;
+    download_assets(app, downloader, &package.asset_index, files, verify_hash).await?;
 
     Ok(())
 }
 
 /// Used to add all file to download on a Downloader
-/// and user can just wait and get status of the current file downloader
+/// and user can just wait and get status of the current file downloader.
+/// Urls are the mojang ones, use `app.downloader()` to download from
+/// the mirrors of the app
 ///
 /// # Examples
-/// ```
+/// ```no_run
+/// # use minecraft_auth::{version::file_to_download_for_version, MinecraftAuth};
+/// # async fn example(app: MinecraftAuth) {
+/// let files = file_to_download_for_version(&app, "1.18.1".into()).await.unwrap();
+/// for download in app.downloader().download_all(files).await {
+///     if let Err(err) = download.result {
+///         println!("{}: {}", download.file.path, err);
+///     }
+/// }
+/// # }
 /// ```
 pub async fn file_to_download_for_version(
     app: &MinecraftAuth,
     version: String,
 ) -> Result<Vec<FileInfo>, error::Error> {
-    let mut files = vec![];
+    file_to_download_with(app, version, &app.downloader(), false).await
+}
 
-    let manifest: ManifestVersion = match manifest(app, "manifest_version") {
-        Ok(manifest) => manifest,
-        _ => {
-            let path = format!("{}/versions", app.path);
-            download_manifest(
-                &path,
-                "https://launchermeta.mojang.com/mc/game/version_manifest.json",
-                "manifest_version",
-            )
-            .await?;
+/// Same as `file_to_download_for_version`, the sha1 of files already
+/// downloaded is checked too. Slower, use it to repair an install
+pub async fn file_to_download_for_version_verified(
+    app: &MinecraftAuth,
+    version: String,
+) -> Result<Vec<FileInfo>, error::Error> {
+    file_to_download_with(app, version, &app.downloader(), true).await
+}
 
-            manifest(app, "manifest_version")?
-        }
-    };
+/// Same as `file_to_download_for_version`, manifests are downloaded
+/// with `downloader` so they can be cancelled with its token
+pub async fn file_to_download_with(
+    app: &MinecraftAuth,
+    version: String,
+    downloader: &Downloader,
+    verify_hash: bool,
+) -> Result<Vec<FileInfo>, error::Error> {
+    let mut files = vec![];
 
-    find_and_install_minecraft_version(app, &version, &manifest.versions, &mut files).await?;
+    let manifest = load_manifest(app, app.manifest_refresh, downloader.cancellation()).await?;
+
+    find_and_install_minecraft_version(
+        app,
+        downloader,
+        &version,
+        &manifest,
+        &mut files,
+        verify_hash,
+    )
+    .await?;
 
     Ok(files)
 }
 
+/// Download all files of a version with `downloader`. Return
+/// `Error::Cancelled` if its token is cancelled before the end.
+/// The bandwidth used is limited by the rate limit of `downloader`
+///
+/// # Examples
+/// ```no_run
+/// # use minecraft_auth::{downloader::CancellationToken, version::install_version, MinecraftAuth};
+/// # async fn example(app: MinecraftAuth) {
+/// let cancel = CancellationToken::new();
+/// let downloader = app.downloader().with_cancellation(cancel.clone());
+///
+/// // Call `cancel.cancel()` from the ui to stop the install,
+/// // and `app.rate_limit.set_limit(Some(500_000))` to slow it down
+/// let downloads = install_version(&app, "1.18.1".into(), &downloader, false).await;
+/// # }
+/// ```
+pub async fn install_version(
+    app: &MinecraftAuth,
+    version: String,
+    downloader: &Downloader,
+    verify_hash: bool,
+) -> Result<Vec<FileDownload>, error::Error> {
+    let files = file_to_download_with(app, version, downloader, verify_hash).await?;
+    let downloads = downloader.download_all(files).await;
+    if downloader.cancellation().is_cancelled() {
+        return Err(error::Error::Cancelled);
+    }
+
+    Ok(downloads)
+}
+
 #[test]
 fn test() {
     let manifest: Package = manifest(
@@ -233,3 +518,239 @@ fn test() {
         }
     });
 }
+
+#[tokio::test]
+async fn cancelled_install() {
+    use crate::{
+        mirror::{Mirror, MirrorConfig},
+        test_server::{serve, Response},
+    };
+    use serde_json::json;
+    use sha1::{Digest, Sha1};
+
+    let index = json!({ "objects": {} }).to_string();
+    let index_sha1 = hex_sha1(index.as_bytes());
+    let url = serve(move |req| {
+        let base = format!("http://{}", req.header("host").unwrap());
+        let artifact = |name: &str, size: u64| {
+            json!({ "sha1": "0".repeat(40), "size": size, "url": format!("{}/{}", base, name) })
+        };
+        match req.path.as_str() {
+            "/1.0.json" => Response::json(
+                200,
+                json!({
+                    "assetIndex": {
+                        "id": "1.0",
+                        "sha1": index_sha1,
+                        "size": index.len(),
+                        "totalSize": 0,
+                        "url": format!("{}/index.json", base),
+                    },
+                    "assets": "1.0",
+                    "complianceLevel": 0,
+                    "downloads": {
+                        "client": artifact("client.jar", 100_000),
+                        "server": artifact("server.jar", 0),
+                    },
+                    "id": "1.0",
+                    "javaVersion": { "component": "java-runtime-gamma", "majorVersion": 17 },
+                    "libraries": [],
+                    "mainClass": "net.minecraft.client.main.Main",
+                    "type": "release",
+                }),
+            ),
+            "/index.json" => Response::new(200, index.clone()),
+            "/client.jar" => Response::new(200, vec![0; 100_000]).hang_after(1_000),
+            _ => Response::json(
+                200,
+                json!({
+                    "latest": { "release": "1.0", "snapshot": "1.0" },
+                    "versions": [{
+                        "id": "1.0",
+                        "type": "release",
+                        "url": format!("{}/1.0.json", base),
+                        "releaseTime": "2022-07-27T09:25:33+00:00",
+                    }],
+                }),
+            ),
+        }
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+    app.mirrors = MirrorConfig::new(vec![
+        Mirror::new("test").rewrite("launchermeta.mojang.com", &url)
+    ]);
+
+    // Cancel once the client is partly downloaded
+    let cancel = CancellationToken::new();
+    let downloader = Downloader::new().with_cancellation(cancel.clone());
+    let part = dir.path().join("clients/1.0/client.jar.part");
+    let (result, _) = tokio::join!(
+        install_version(&app, "1.0".into(), &downloader, false),
+        async {
+            while !part.exists() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            cancel.cancel();
+        }
+    );
+    assert!(matches!(result, Err(Error::Cancelled)));
+    assert!(dir.path().join("versions/1.0.json").exists());
+
+    fn leftovers(dir: &Path) -> Vec<String> {
+        fs::read_dir(dir)
+            .unwrap()
+            .flat_map(|entry| {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    return leftovers(&path);
+                }
+                let name = path.to_string_lossy().to_string();
+                if name.ends_with(".part") || name.ends_with(".tmp") {
+                    vec![name]
+                } else {
+                    vec![]
+                }
+            })
+            .collect()
+    }
+    assert_eq!(leftovers(dir.path()), Vec::<String>::new());
+
+    fn hex_sha1(content: &[u8]) -> String {
+        Sha1::digest(content)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn manifest_refresh() {
+    use crate::{
+        mirror::{Mirror, MirrorConfig},
+        test_server::{serve, Response},
+    };
+    use serde_json::json;
+    use sha1::{Digest, Sha1};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let count = requests.clone();
+    let url = serve(move |req| {
+        let n = count.fetch_add(1, Ordering::SeqCst);
+        match req.path.as_str() {
+            "/1.0.json" => Response::new(200, "new"),
+            _ if req.header("if-none-match") == Some("\"v1\"") => Response::new(304, ""),
+            _ => Response::json(
+                200,
+                json!({
+                    "latest": { "release": format!("1.{}", n), "snapshot": "1.1" },
+                    "versions": [{
+                        "id": "1.0",
+                        "type": "release",
+                        "url": format!("http://{}/1.0.json", req.header("host").unwrap()),
+                        "sha1": "c2a4f7bdb4f3ac5a82d6d2c4f11f3f7cfd8f6c0c",
+                        "time": "2022-07-28T01:16:55+00:00",
+                        "releaseTime": "2022-07-27T09:25:33+00:00",
+                    }],
+                }),
+            )
+            .header("ETag", "\"v1\""),
+        }
+    })
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+    app.mirrors = MirrorConfig {
+        mirrors: vec![Mirror::new("test").rewrite("launchermeta.mojang.com", &url)],
+        fallback_to_origin: false,
+    };
+
+    let list = versions_manifest(&app, ManifestRefresh::default())
+        .await
+        .unwrap();
+    assert_eq!(list.latest.release, "1.0");
+    assert_eq!(list.versions[0].release_time, "2022-07-27T09:25:33+00:00");
+    versions_manifest(&app, ManifestRefresh::default())
+        .await
+        .unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    // Not modified, the saved list is kept
+    let list = versions_manifest(&app, ManifestRefresh::Always)
+        .await
+        .unwrap();
+    assert_eq!(list.latest.release, "1.0");
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    // The saved list is used when the server can't be reached
+    let mut offline = app.clone();
+    offline.mirrors.mirrors =
+        vec![Mirror::new("down").rewrite("launchermeta.mojang.com", "http://127.0.0.1:1")];
+    let list = versions_manifest(&offline, ManifestRefresh::Always)
+        .await
+        .unwrap();
+    assert_eq!(list.versions.len(), 1);
+
+    // Manifests changed by mojang are downloaded again
+    let version_path = dir.path().join("versions/1.0.json");
+    fs::write(&version_path, "old").unwrap();
+    let mut version = list.versions[0].clone();
+    let sha1 = Sha1::digest(b"new");
+    version.sha1 = Some(sha1.iter().map(|b| format!("{:02x}", b)).collect());
+    update_version_manifest(&app, &Downloader::new(), &version)
+        .await
+        .unwrap();
+    assert_eq!(fs::read_to_string(&version_path).unwrap(), "new");
+    update_version_manifest(&app, &Downloader::new(), &version)
+        .await
+        .unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn filter_versions() {
+    use serde_json::json;
+
+    let manifest: ManifestVersion = serde_json::from_value(json!({
+        "latest": { "release": "1.19", "snapshot": "22w24a" },
+        "versions": [
+            { "id": "22w24a", "type": "snapshot", "url": "", "releaseTime": "2022-06-15T16:24:44+00:00" },
+            { "id": "1.19", "type": "release", "url": "", "releaseTime": "2022-06-07T09:42:18+00:00" },
+            { "id": "b1.7.3", "type": "old_beta", "url": "", "releaseTime": "2011-07-07T22:00:00+00:00" },
+            { "id": "1.18.2", "type": "release", "url": "", "releaseTime": "2022-02-28T10:42:45+00:00" },
+        ],
+    }))
+    .unwrap();
+
+    assert_eq!(manifest.latest_release().unwrap().id, "1.19");
+    assert_eq!(manifest.find("latest-snapshot").unwrap().id, "22w24a");
+    assert_eq!(manifest.versions[2].version_type(), VersionType::OldBeta);
+    assert_eq!(manifest.versions[1].release_time_secs(), Some(1654594938));
+
+    let dir = tempfile::tempdir().unwrap();
+    let app = MinecraftAuth::new("test".into(), dir.path().to_str().unwrap().into());
+    fs::create_dir_all(dir.path().join("versions")).unwrap();
+    fs::create_dir_all(dir.path().join("clients/1.18.2")).unwrap();
+    fs::write(dir.path().join("versions/1.18.2.json"), "{}").unwrap();
+    fs::write(dir.path().join("clients/1.18.2/client.jar"), "").unwrap();
+
+    let releases = VersionFilter::types(&[VersionType::Release]).apply(&app, &manifest);
+    let ids: Vec<&str> = releases.iter().map(|e| e.version.id.as_str()).collect();
+    assert_eq!(ids, vec!["1.19", "1.18.2"]);
+    assert!(!releases[0].installed && releases[1].installed);
+
+    let installed = VersionFilter {
+        installed_only: true,
+        ..Default::default()
+    }
+    .apply(&app, &manifest);
+    assert_eq!(installed.len(), 1);
+    assert_eq!(VersionFilter::default().apply(&app, &manifest).len(), 4);
+}